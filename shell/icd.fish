@@ -0,0 +1,16 @@
+# `icd`: browse with itree, then cd to the directory chosen with Enter.
+#
+# To install, copy this file to ~/.config/fish/functions/icd.fish.
+#
+# Any arguments are passed through to itree.
+function icd --description 'Browse with itree, then cd to the chosen directory'
+    set -l tmp (mktemp -t itree-choosedir.XXXXXX); or return 1
+
+    itree --choosedir=$tmp $argv
+    set -l dir (cat -- $tmp)
+    rm -f -- $tmp
+
+    if test -n "$dir"; and test "$dir" != "$PWD"
+        cd -- $dir
+    end
+end
@@ -0,0 +1,19 @@
+# `icd`: browse with itree, then cd to the directory chosen with Enter.
+#
+# Works in bash and zsh. To install, source this file from your ~/.bashrc or ~/.zshrc:
+#
+#     source /path/to/itree/shell/icd.sh
+#
+# Any arguments are passed through to itree.
+icd() {
+    local tmp dir
+    tmp="$(mktemp -t itree-choosedir.XXXXXX)" || return 1
+
+    itree --choosedir="$tmp" "$@"
+    dir="$(cat -- "$tmp")"
+    rm -f -- "$tmp"
+
+    if [ -n "$dir" ] && [ "$dir" != "$PWD" ]; then
+        cd -- "$dir"
+    fi
+}
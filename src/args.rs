@@ -1,11 +1,18 @@
-use itree::{color, options};
+use itree::{color, options, search};
 
 use clap::{App, Arg};
 
 pub enum RenderMethod {
     JustSummary,
     NoInteractive,
-    FullInteractive,
+    Json,
+    /// Navigate interactively. If the user chooses an entry, write its directory
+    /// to `choosedir` if given, or print its path otherwise.
+    /// Follow changes to the FS if `watch` is set.
+    FullInteractive {
+        choosedir: Option<String>,
+        watch: bool,
+    },
 }
 
 pub fn parse_args(
@@ -22,9 +29,23 @@ pub fn parse_args(
         .args(&[
             no_interact_arg(),
             quiet_arg(),
+            json_arg(),
+            choosedir_arg(),
             only_dirs_arg(),
+            pattern_arg(),
+            size_arg(),
+            human_arg(),
             level_arg(),
+            sort_arg(),
+            reverse_arg(),
+            dirs_first_arg(),
+            lazy_arg(),
+            watch_arg(),
+            wrap_arg(),
             link_arg(),
+            resolve_links_arg(),
+            escape_arg(),
+            one_file_system_arg(),
             filesize_arg(),
             hidden_arg(),
             no_ignore_arg(),
@@ -32,6 +53,8 @@ pub fn parse_args(
             custom_ignore_arg(),
             bg_color_arg(),
             fg_color_arg(),
+            match_color_arg(),
+            change_color_arg(),
             root_arg(),
         ])
         .get_matches();
@@ -43,7 +66,13 @@ pub fn parse_args(
                 .value_of("max_level")
                 .map(|s| s.parse::<usize>().unwrap()),
         )
+        .lazy_depth(
+            matches
+                .value_of("lazy_level")
+                .map(|s| s.parse::<usize>().unwrap()),
+        )
         .follow_links(matches.is_present("follow_links"))
+        .same_file_system(matches.is_present("one_file_system"))
         .max_filesize(
             matches
                 .value_of("max_filesize")
@@ -51,8 +80,17 @@ pub fn parse_args(
         )
         .hidden(matches.is_present("hidden"))
         .only_dirs(matches.is_present("only_dirs"))
+        .escape_names(matches.is_present("escape"))
         .no_ignore(matches.is_present("no_ignore"))
-        .no_git_exclude(matches.is_present("no_git_exclude"));
+        .no_git_exclude(matches.is_present("no_git_exclude"))
+        .sort_by(
+            matches
+                .value_of("sort")
+                .map(|s| options::SortBy::from_name(s).unwrap())
+                .unwrap_or(options::SortBy::Name),
+        )
+        .reverse(matches.is_present("reverse"))
+        .dirs_first(matches.is_present("dirs_first"));
 
     if let Some(files) = matches.values_of("custom_ignore") {
         for file in files {
@@ -71,15 +109,40 @@ pub fn parse_args(
         ))
         .bg_color(string_to_color(
             matches.value_of("bg_color").unwrap_or("blue"),
-        ));
+        ))
+        .match_color(string_to_color(
+            matches.value_of("match_color").unwrap_or("yellow"),
+        ))
+        .change_color(string_to_color(
+            matches.value_of("change_color").unwrap_or("green"),
+        ))
+        .resolve_links(matches.is_present("resolve_links"))
+        .truncate(!matches.is_present("wrap"))
+        .filter(
+            matches
+                .value_of("pattern")
+                .map(|p| search::Matcher::new(p, search::MatchKind::Glob).unwrap()),
+        )
+        .size(if matches.is_present("human") {
+            Some(options::SizeFormat::Human)
+        } else if matches.is_present("size") {
+            Some(options::SizeFormat::Bytes)
+        } else {
+            None
+        });
 
     let rm: RenderMethod;
     if matches.is_present("quiet") {
         rm = RenderMethod::JustSummary;
+    } else if matches.is_present("json") {
+        rm = RenderMethod::Json;
     } else if matches.is_present("no_interact") {
         rm = RenderMethod::NoInteractive;
     } else {
-        rm = RenderMethod::FullInteractive;
+        rm = RenderMethod::FullInteractive {
+            choosedir: matches.value_of("choosedir").map(|s| s.to_owned()),
+            watch: matches.is_present("watch"),
+        };
     }
 
     (fs_options, rd_options, rm)
@@ -106,6 +169,10 @@ fn colors() -> &'static [&'static str] {
     ]
 }
 
+fn sorts() -> &'static [&'static str] {
+    &["name", "natural", "size", "mtime", "ctime", "ext", "none"]
+}
+
 fn string_to_color(cs: &str) -> Box<color::Color> {
     match cs {
         "black" => Box::new(color::Black),
@@ -143,12 +210,55 @@ fn quiet_arg<'a, 'b>() -> Arg<'a, 'b> {
         .conflicts_with("no_interact")
 }
 
+fn json_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("json")
+        .short("J")
+        .long("json")
+        .help("Do not enter interactive mode - print the tree and summary information as JSON.")
+        .conflicts_with("quiet")
+}
+
+fn choosedir_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("choosedir")
+        .long("choosedir")
+        .help("When exiting with Enter, write the directory under the cursor to this file, instead of printing its path.")
+        .takes_value(true)
+        .value_name("file")
+        .conflicts_with_all(&["quiet", "no_interact", "json"])
+}
+
 fn only_dirs_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("only_dirs")
         .long("only-dirs")
         .help("List directories only")
 }
 
+fn pattern_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("pattern")
+        .short("P")
+        .long("pattern")
+        .help("List only files matching the glob pattern, and the directories containing them")
+        .takes_value(true)
+        .validator(|s| {
+            search::Matcher::new(&s, search::MatchKind::Glob)
+                .map(|_| {})
+                .map_err(|e| format!("Error parsing pattern: {}", e))
+        })
+}
+
+fn size_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("size")
+        .short("s")
+        .long("size")
+        .help("Print the size of each file, and the total size of each directory, in bytes")
+}
+
+fn human_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("human")
+        .long("human")
+        .help("Print sizes in a human readable format (e.g. 4.0K, 12M). Implies --size")
+}
+
 fn level_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("max_level")
         .short("L")
@@ -158,6 +268,56 @@ fn level_arg<'a, 'b>() -> Arg<'a, 'b> {
         .validator(|s| s.parse::<usize>().map(|_| {}).map_err(|e| format!("{}", e)))
 }
 
+fn sort_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("sort")
+        .long("sort")
+        .help("The order of the entries in each directory. Sizes and times sort largest and newest first. Name by default")
+        .takes_value(true)
+        .value_name("order")
+        .possible_values(sorts())
+}
+
+fn reverse_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("reverse")
+        .short("r")
+        .long("reverse")
+        .help("Reverse the order of the entries in each directory")
+}
+
+fn dirs_first_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("dirs_first")
+        .long("dirs-first")
+        .help("List directories before files")
+}
+
+fn lazy_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("lazy_level")
+        .long("lazy")
+        .help("Read only this many levels up front, and deeper directories as they are opened")
+        .takes_value(true)
+        .value_name("level")
+        .validator(|s| match s.parse::<usize>() {
+            Ok(0) => Err("must be at least 1".to_owned()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{}", e)),
+        })
+        .conflicts_with_all(&["quiet", "no_interact", "json"])
+}
+
+fn watch_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("watch")
+        .long("watch")
+        .help("Keep the tree up to date as files are created, removed and renamed (Linux only)")
+        .conflicts_with_all(&["quiet", "no_interact", "json"])
+}
+
+fn wrap_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("wrap")
+        .long("wrap")
+        .help("Wrap lines too long for the terminal, rather than cutting them short with …")
+        .conflicts_with_all(&["quiet", "no_interact", "json"])
+}
+
 fn link_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("follow_links")
         .short("l")
@@ -165,6 +325,25 @@ fn link_arg<'a, 'b>() -> Arg<'a, 'b> {
         .help("Follow links")
 }
 
+fn resolve_links_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("resolve_links")
+        .long("resolve-links")
+        .help("Show the absolute path each link resolves to, rather than its target as written")
+}
+
+fn escape_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("escape")
+        .long("escape")
+        .help("Escape control characters and invalid UTF-8 in names, e.g. as \\n or \\xff")
+}
+
+fn one_file_system_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("one_file_system")
+        .short("x")
+        .long("one-file-system")
+        .help("Stay on the file system of the root, marking directories that others are mounted on")
+}
+
 fn filesize_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("max_filesize")
         .long("max-filesize")
@@ -220,6 +399,22 @@ fn fg_color_arg<'a, 'b>() -> Arg<'a, 'b> {
         .possible_values(colors())
 }
 
+fn match_color_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("match_color")
+        .long("match-color")
+        .help("The foreground color to highlight search matches. Yellow by default")
+        .takes_value(true)
+        .possible_values(colors())
+}
+
+fn change_color_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("change_color")
+        .long("change-color")
+        .help("The foreground color to highlight entries changed while watching. Green by default")
+        .takes_value(true)
+        .possible_values(colors())
+}
+
 fn root_arg<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("root")
         .index(1)
@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use termion::event::Key;
+
+/// Something the user can do in the interactive navigator, by pressing a bound key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    FocusUp,
+    FocusDown,
+    FocusLeft,
+    FocusRight,
+    Fold,
+    Search,
+    Filter,
+    SearchNext,
+    SearchPrev,
+    Edit,
+    View,
+    Errors,
+    Refresh,
+    Rebuild,
+    Sort,
+    ScrollLeft,
+    ScrollRight,
+    Choose,
+    Quit,
+}
+
+const ACTIONS: &[Action] = &[
+    Action::FocusUp,
+    Action::FocusDown,
+    Action::FocusLeft,
+    Action::FocusRight,
+    Action::Fold,
+    Action::Search,
+    Action::Filter,
+    Action::SearchNext,
+    Action::SearchPrev,
+    Action::Edit,
+    Action::View,
+    Action::Errors,
+    Action::Refresh,
+    Action::Rebuild,
+    Action::Sort,
+    Action::ScrollLeft,
+    Action::ScrollRight,
+    Action::Choose,
+    Action::Quit,
+];
+
+impl Action {
+    /// The name by which the action is bound in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::FocusUp => "focus_up",
+            Action::FocusDown => "focus_down",
+            Action::FocusLeft => "focus_left",
+            Action::FocusRight => "focus_right",
+            Action::Fold => "fold",
+            Action::Search => "search",
+            Action::Filter => "filter",
+            Action::SearchNext => "search_next",
+            Action::SearchPrev => "search_prev",
+            Action::Edit => "edit",
+            Action::View => "view",
+            Action::Errors => "errors",
+            Action::Refresh => "refresh",
+            Action::Rebuild => "rebuild",
+            Action::Sort => "sort",
+            Action::ScrollLeft => "scroll_left",
+            Action::ScrollRight => "scroll_right",
+            Action::Choose => "choose",
+            Action::Quit => "quit",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ACTIONS.iter().cloned().find(|a| a.name() == name)
+    }
+}
+
+/// A mapping from keys to the actions they perform.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyMap {
+    keys: HashMap<Key, Action>,
+}
+
+impl Default for KeyMap {
+    /// The built-in bindings: arrow keys and vim keys to move around, plus
+    /// mnemonics for everything else.
+    fn default() -> Self {
+        let bindings = [
+            (Key::Left, Action::FocusUp),
+            (Key::Char('h'), Action::FocusUp),
+            (Key::Right, Action::FocusDown),
+            (Key::Char('l'), Action::FocusDown),
+            (Key::Up, Action::FocusLeft),
+            (Key::Char('k'), Action::FocusLeft),
+            (Key::Down, Action::FocusRight),
+            (Key::Char('j'), Action::FocusRight),
+            (Key::Char('f'), Action::Fold),
+            (Key::Char('/'), Action::Search),
+            (Key::Char('&'), Action::Filter),
+            (Key::Char('n'), Action::SearchNext),
+            (Key::Char('N'), Action::SearchPrev),
+            (Key::Char('e'), Action::Edit),
+            (Key::Char('v'), Action::View),
+            (Key::Char('!'), Action::Errors),
+            (Key::Char('r'), Action::Refresh),
+            (Key::Char('R'), Action::Rebuild),
+            (Key::Char('s'), Action::Sort),
+            (Key::Char('<'), Action::ScrollLeft),
+            (Key::Char('>'), Action::ScrollRight),
+            (Key::Char('\n'), Action::Choose),
+            (Key::Esc, Action::Quit),
+            (Key::Char('q'), Action::Quit),
+            (Key::Ctrl('c'), Action::Quit),
+        ];
+
+        Self {
+            keys: bindings.iter().cloned().collect(),
+        }
+    }
+}
+
+impl KeyMap {
+    pub fn get(&self, key: &Key) -> Option<Action> {
+        self.keys.get(key).cloned()
+    }
+
+    /// Whether any key is bound to `action`.
+    pub fn is_bound(&self, action: Action) -> bool {
+        self.keys.values().any(|&a| a == action)
+    }
+
+    /// Bind `key` to `action`, replacing any existing binding, or unbind it if
+    /// `action` is `None`.
+    pub fn bind(&mut self, key: Key, action: Option<Action>) {
+        match action {
+            Some(a) => self.keys.insert(key, a),
+            None => self.keys.remove(&key),
+        };
+    }
+}
+
+/// User configuration, read from `$XDG_CONFIG_HOME/itree/config`
+/// (by default, `~/.config/itree/config`).
+///
+/// The file is line-based, in a TOML-like format of its own: `[section]`
+/// headers, `name = "value"` entries, and `#` comments, each on a line of its
+/// own or after an entry. Names are bare (letters, digits, `-` and `_`) or
+/// quoted, and values are always quoted; quoted strings may use the escapes
+/// `\"`, `\\`, `\n` and `\t`. Nothing else is supported.
+///
+/// For now, it has just a `[keys]` section, whose entries bind key chords to
+/// actions on top of the defaults. Some key must still be bound to `quit`:
+///
+/// ```text
+/// [keys]
+/// ctrl-n = "focus_right"
+/// "ctrl-p" = "focus_left"
+/// q = "none"  # Unbind q
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Config {
+    pub keys: KeyMap,
+}
+
+/// An error in a config file, with the line it was found on.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl Config {
+    /// The path the config file is read from, if a home directory can be found.
+    pub fn path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+            .map(|d| d.join("itree").join("config"))
+    }
+
+    /// Load the config file, or the default config if there is no file.
+    pub fn load() -> Result<Self, String> {
+        let path = match Config::path() {
+            Some(p) => p,
+            None => return Ok(Config::default()),
+        };
+
+        let mut contents = String::new();
+        match File::open(&path).and_then(|mut f| f.read_to_string(&mut contents)) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        }
+
+        Config::parse(&contents).map_err(|e| format!("Invalid config in {}, {}", path.display(), e))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+        let mut in_section = false;
+        let mut seen = HashMap::new();
+        // The last line to take a key away from `quit`, to blame if none is left.
+        let mut unbound_quit = 0;
+
+        for (i, line) in contents.lines().enumerate() {
+            let n = i + 1;
+            let err = |msg: String| ConfigError { line: n, msg };
+
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(err("expected `]` to end the section header".to_owned()));
+                }
+                let name = line[1..line.len() - 1].trim();
+                if name != "keys" {
+                    return Err(err(format!("unknown section `[{}]`", name)));
+                }
+                in_section = true;
+                continue;
+            }
+
+            let eq = match find_unquoted(line, '=') {
+                Some(eq) => eq,
+                None => return Err(err("expected `<key> = \"<action>\"`".to_owned())),
+            };
+            let chord = parse_name(line[..eq].trim()).map_err(&err)?;
+            let value = parse_string(line[eq + 1..].trim()).map_err(&err)?;
+
+            if !in_section {
+                return Err(err(format!(
+                    "`{}` must be in a section, e.g. `[keys]`",
+                    chord
+                )));
+            }
+
+            let key = parse_key(&chord).map_err(&err)?;
+            if let Some(prev) = seen.insert(key, n) {
+                return Err(err(format!(
+                    "`{}` is already bound on line {}",
+                    chord, prev
+                )));
+            }
+
+            let action = if value == "none" {
+                None
+            } else {
+                match Action::from_name(&value) {
+                    Some(a) => Some(a),
+                    None => {
+                        return Err(err(format!(
+                            "unknown action `{}`; expected one of {}, or `none`",
+                            value,
+                            ACTIONS
+                                .iter()
+                                .map(|a| format!("`{}`", a.name()))
+                                .collect::<Vec<String>>()
+                                .join(", ")
+                        )))
+                    }
+                }
+            };
+            if config.keys.get(&key) == Some(Action::Quit) && action != Some(Action::Quit) {
+                unbound_quit = n;
+            }
+            config.keys.bind(key, action);
+        }
+
+        if !config.keys.is_bound(Action::Quit) {
+            return Err(ConfigError {
+                line: unbound_quit,
+                msg: "no key is left bound to `quit`".to_owned(),
+            });
+        }
+
+        Ok(config)
+    }
+}
+
+/// Parse a key chord, like `j`, `ctrl-c`, `alt-x`, `enter`, or `f5`.
+pub fn parse_key(chord: &str) -> Result<Key, String> {
+    let single = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+
+    if let Some(c) = single(chord) {
+        return Ok(Key::Char(c));
+    }
+
+    let lower = chord.to_lowercase();
+    if lower.starts_with("ctrl-") {
+        if let Some(c) = single(&chord[5..]) {
+            return Ok(Key::Ctrl(c.to_ascii_lowercase()));
+        }
+    } else if lower.starts_with("alt-") {
+        if let Some(c) = single(&chord[4..]) {
+            return Ok(Key::Alt(c));
+        }
+    } else if let Some(n) = lower.strip_prefix('f') {
+        if let Ok(n) = n.parse::<u8>() {
+            if (1..=12).contains(&n) {
+                return Ok(Key::F(n));
+            }
+        }
+    }
+
+    Ok(match lower.as_str() {
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "backspace" => Key::Backspace,
+        "delete" => Key::Delete,
+        "insert" => Key::Insert,
+        "esc" => Key::Esc,
+        "enter" => Key::Char('\n'),
+        "tab" => Key::Char('\t'),
+        "space" => Key::Char(' '),
+        _ => return Err(format!("unknown key `{}`", chord)),
+    })
+}
+
+fn strip_comment(line: &str) -> &str {
+    match find_unquoted(line, '#') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+/// Find the first occurrence of `target` outside of a quoted string.
+fn find_unquoted(line: &str, target: char) -> Option<usize> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == target && !quoted => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parse an entry's name, either bare (`ctrl-c`) or quoted (`"/"`).
+fn parse_name(s: &str) -> Result<String, String> {
+    if s.starts_with('"') {
+        return parse_string(s);
+    }
+
+    if s.is_empty() {
+        return Err("expected a key before `=`".to_owned());
+    }
+    if !s
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!("`{}` must be quoted, e.g. `\"{}\"`", s, s));
+    }
+    Ok(s.to_owned())
+}
+
+/// Parse a quoted string (`"..."`), handling the common escapes.
+fn parse_string(s: &str) -> Result<String, String> {
+    if s.len() < 2 || !s.starts_with('"') || !s.ends_with('"') {
+        return Err(format!("expected a quoted string, found `{}`", s));
+    }
+
+    let mut out = String::new();
+    let mut chars = s[1..s.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Err(format!("unexpected `\"` in {}", s)),
+            '\\' => out.push(match chars.next() {
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some(e) => return Err(format!("unknown escape `\\{}` in {}", e, s)),
+                None => return Err(format!("unterminated string {}", s)),
+            }),
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(Ok(Key::Char('j')), parse_key("j"));
+        assert_eq!(Ok(Key::Char('/')), parse_key("/"));
+        assert_eq!(Ok(Key::Ctrl('c')), parse_key("ctrl-c"));
+        assert_eq!(Ok(Key::Ctrl('c')), parse_key("Ctrl-C"));
+        assert_eq!(Ok(Key::Alt('x')), parse_key("alt-x"));
+        assert_eq!(Ok(Key::F(5)), parse_key("f5"));
+        assert_eq!(Ok(Key::Char('\n')), parse_key("enter"));
+        assert_eq!(Ok(Key::Left), parse_key("Left"));
+        assert!(parse_key("f13").is_err());
+        assert!(parse_key("ctrl-").is_err());
+        assert!(parse_key("hyper-x").is_err());
+    }
+
+    #[test]
+    fn test_default_keys() {
+        let keys = KeyMap::default();
+        assert_eq!(Some(Action::FocusUp), keys.get(&Key::Char('h')));
+        assert_eq!(Some(Action::ScrollRight), keys.get(&Key::Char('>')));
+        assert_eq!(Some(Action::FocusRight), keys.get(&Key::Down));
+        assert_eq!(Some(Action::Quit), keys.get(&Key::Ctrl('c')));
+        assert_eq!(None, keys.get(&Key::Char('z')));
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(
+            "# My keys\n\
+             [keys]\n\
+             ctrl-n = \"focus_right\"  # Like emacs\n\
+             \"#\" = \"fold\"\n\
+             q = \"none\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(Some(Action::FocusRight), config.keys.get(&Key::Ctrl('n')));
+        assert_eq!(Some(Action::Fold), config.keys.get(&Key::Char('#')));
+        assert_eq!(None, config.keys.get(&Key::Char('q')));
+        assert_eq!(Some(Action::FocusRight), config.keys.get(&Key::Char('j')));
+
+        assert_eq!(Ok(Config::default()), Config::parse(""));
+    }
+
+    #[test]
+    fn test_parse_config_errors() {
+        let line_of = |s| Config::parse(s).unwrap_err().line;
+
+        assert_eq!(1, line_of("[colors]"));
+        assert_eq!(1, line_of("j = \"focus_right\""));
+        assert_eq!(2, line_of("[keys]\nj = \"jump\""));
+        assert_eq!(2, line_of("[keys]\nj = focus_right"));
+        assert_eq!(2, line_of("[keys]\nhyper-j = \"fold\""));
+        assert_eq!(3, line_of("[keys]\nj = \"fold\"\n\"j\" = \"quit\""));
+        assert_eq!(2, line_of("[keys]\n/ = \"search\""));
+
+        let unbind_quit = "[keys]\nq = \"none\"\nesc = \"fold\"\nctrl-c = \"none\"\n";
+        assert_eq!(4, line_of(unbind_quit));
+        assert!(Config::parse(&format!("{}x = \"quit\"", unbind_quit)).is_ok());
+
+        let e = Config::parse("[keys]\nj = \"jump\"").unwrap_err();
+        assert!(e.to_string().starts_with("line 2: unknown action `jump`"));
+    }
+}
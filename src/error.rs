@@ -0,0 +1,131 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ignore;
+
+/// An error reading the filesystem into a tree.
+///
+/// Errors reading the root keep the tree from being built at all. Errors
+/// reading anything beneath it leave that entry out of the tree, and are
+/// collected in `Tree::errors`.
+#[derive(Debug)]
+pub enum ItreeError {
+    /// The path does not exist.
+    NotFound(PathBuf),
+    /// The path could not be read, for lack of permission.
+    PermissionDenied(PathBuf),
+    /// An ignore pattern could not be parsed.
+    BadIgnorePattern { pattern: String, msg: String },
+    /// Any other error, at the path given if it is known.
+    Io {
+        path: Option<PathBuf>,
+        err: io::Error,
+    },
+}
+
+impl ItreeError {
+    /// The path the error occurred at, if it is known.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            ItreeError::NotFound(path) | ItreeError::PermissionDenied(path) => Some(path),
+            ItreeError::BadIgnorePattern { .. } => None,
+            ItreeError::Io { path, .. } => path.as_ref().map(|p| p.as_path()),
+        }
+    }
+
+    fn from_ignore(path: Option<PathBuf>, e: ignore::Error) -> Self {
+        match e {
+            ignore::Error::WithPath { path, err } => ItreeError::from_ignore(Some(path), *err),
+            ignore::Error::WithDepth { err, .. } => ItreeError::from_ignore(path, *err),
+            ignore::Error::WithLineNumber { err, .. } => ItreeError::from_ignore(path, *err),
+            ignore::Error::Io(err) => match (err.kind(), path) {
+                (io::ErrorKind::NotFound, Some(path)) => ItreeError::NotFound(path),
+                (io::ErrorKind::PermissionDenied, Some(path)) => {
+                    ItreeError::PermissionDenied(path)
+                }
+                (_, path) => ItreeError::Io { path, err },
+            },
+            ignore::Error::Loop { ancestor, child } => ItreeError::Io {
+                err: io::Error::other(format!(
+                    "File system loop found: {} points to an ancestor {}",
+                    child.display(),
+                    ancestor.display()
+                )),
+                path: Some(child),
+            },
+            ignore::Error::Glob { glob, err } => ItreeError::BadIgnorePattern {
+                pattern: glob.unwrap_or_default(),
+                msg: err,
+            },
+            e => ItreeError::Io {
+                path,
+                err: io::Error::other(e.to_string()),
+            },
+        }
+    }
+}
+
+impl From<ignore::Error> for ItreeError {
+    fn from(e: ignore::Error) -> Self {
+        ItreeError::from_ignore(None, e)
+    }
+}
+
+impl fmt::Display for ItreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ItreeError::NotFound(path) => write!(f, "Path \"{}\" was not found", path.display()),
+            ItreeError::PermissionDenied(path) => {
+                write!(f, "Permission denied reading \"{}\"", path.display())
+            }
+            ItreeError::BadIgnorePattern { pattern, msg } => {
+                write!(f, "Error parsing ignore \"{}\": {}", pattern, msg)
+            }
+            ItreeError::Io {
+                path: Some(path),
+                err,
+            } => write!(f, "Error reading \"{}\": {}", path.display(), err),
+            ItreeError::Io { path: None, err } => write!(f, "Error reading the tree: {}", err),
+        }
+    }
+}
+
+impl Error for ItreeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_ignore() {
+        let with_path = |kind| ignore::Error::WithDepth {
+            depth: 1,
+            err: Box::new(ignore::Error::WithPath {
+                path: PathBuf::from("a/b"),
+                err: Box::new(ignore::Error::Io(io::Error::new(kind, "oops"))),
+            }),
+        };
+
+        match ItreeError::from(with_path(io::ErrorKind::NotFound)) {
+            ItreeError::NotFound(path) => assert_eq!(PathBuf::from("a/b"), path),
+            e => panic!("Unexpected error {:?}", e),
+        }
+        match ItreeError::from(with_path(io::ErrorKind::PermissionDenied)) {
+            ItreeError::PermissionDenied(path) => assert_eq!(PathBuf::from("a/b"), path),
+            e => panic!("Unexpected error {:?}", e),
+        }
+        match ItreeError::from(with_path(io::ErrorKind::Other)) {
+            ItreeError::Io { path, .. } => assert_eq!(Some(PathBuf::from("a/b")), path),
+            e => panic!("Unexpected error {:?}", e),
+        }
+        match ItreeError::from(ignore::Error::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            "oops",
+        ))) {
+            ItreeError::Io { path: None, .. } => {}
+            e => panic!("Unexpected error {:?}", e),
+        }
+    }
+}
@@ -1,54 +1,158 @@
-use std::ffi::OsStr;
-use std::fs::{metadata, read_link};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{canonicalize, metadata, read_link};
 use std::io;
+use std::iter::Peekable;
+use std::mem;
 use std::ops::Deref;
-use std::path::Path;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use options::FsOptions;
-use util::PutBack;
+use error::ItreeError;
+use options::{FsOptions, SortBy, SortOrder};
 
-use ignore::{self, overrides::OverrideBuilder, DirEntry, Walk, WalkBuilder};
+use ignore::{self, overrides::OverrideBuilder, DirEntry, WalkBuilder, WalkState};
 use indextree::{Arena, NodeId};
+use libc;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum FileType {
     File,
     Dir,
     RestrictedDir,
+    /// A directory on another file system, left unread because the walk
+    /// stays on one file system.
+    MountPoint,
     Stdin,
-    LinkTo(String),
+    /// A link to `target`, as read from the link, which may be relative to
+    /// the directory containing it.
+    LinkTo { target: PathBuf, kind: LinkKind },
+}
+
+/// What a link points to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LinkKind {
+    File,
+    Dir,
+    /// Nothing, because the target doesn't exist.
+    Broken,
+    /// A directory containing the link, or a chain of links leading back to
+    /// itself, so that it can't be followed.
+    Loop,
+}
+
+/// Metadata captured once per entry while walking the filesystem.
+///
+/// Times are in seconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsMetadata {
+    pub size: u64,
+    pub mtime: i64,
+    pub ctime: i64,
+    pub atime: i64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub inode: u64,
+    pub nlink: u64,
+    /// The device of the file system the entry is on.
+    pub dev: u64,
+}
+
+impl FsMetadata {
+    fn from_de(de: &DirEntry) -> Option<Self> {
+        de.metadata().ok().map(|m| Self {
+            size: m.len(),
+            mtime: m.mtime(),
+            ctime: m.ctime(),
+            atime: m.atime(),
+            mode: m.mode(),
+            uid: m.uid(),
+            gid: m.gid(),
+            inode: m.ino(),
+            nlink: m.nlink(),
+            dev: m.dev(),
+        })
+    }
 }
 
 #[derive(Debug)]
 pub struct FsEntry {
     pub ft: FileType,
     pub de: DirEntry,
-    pub name: String,
+    /// The entry's file name, or for the root, the path it was read at.
+    ///
+    /// This needn't be valid UTF-8; see `escape_name` for showing it.
+    pub name: OsString,
+    /// `None` if the entry could not be stat-ed, or is stdin.
+    pub meta: Option<FsMetadata>,
+    /// The size of this entry - for directories, including everything beneath them.
+    ///
+    /// Only meaningful once `aggregate_sizes` has run over the tree.
+    pub total_size: u64,
 }
 
-/// Create an iterator over the FS, rooted at dir.
-fn get_walker<P: AsRef<Path>>(options: &FsOptions<P>) -> Walk {
-    let mut builder = WalkBuilder::new(&options.root);
+/// Create a walker over the FS, rooted at `dir` and at most `max_depth` levels deep.
+///
+/// `dir` is either `options.root` or a directory beneath it, in which case ignore
+/// files in the directories between the two are still respected.
+///
+/// Fails if `options.custom_ignore` has a pattern that can't be parsed.
+fn get_walker<P: AsRef<Path>>(
+    options: &FsOptions<P>,
+    dir: &Path,
+    max_depth: Option<usize>,
+) -> Result<WalkBuilder, ItreeError> {
+    let mut builder = WalkBuilder::new(dir);
 
     builder
         .parents(false)
-        .sort_by_file_name(|f1, f2| f1.cmp(f2))
+        .max_depth(max_depth)
         .follow_links(options.follow_links)
         .max_filesize(options.max_filesize)
+        .same_file_system(options.same_file_system)
         .hidden(!options.hidden)
         .ignore(!options.no_ignore)
         .git_global(!options.no_ignore)
         .git_ignore(!options.no_ignore)
         .git_exclude(!options.no_git_exclude);
 
+    // Outermost first, so that nearer ignore files take precedence.
+    let mut between = dir.ancestors()
+        .skip(1)
+        .take_while(|a| a.starts_with(&options.root))
+        .collect::<Vec<&Path>>();
+    between.reverse();
+    for a in between {
+        let mut files = Vec::new();
+        if !options.no_ignore {
+            files.push(a.join(".gitignore"));
+            files.push(a.join(".ignore"));
+        }
+        if !options.no_git_exclude {
+            files.push(a.join(".git/info/exclude"));
+        }
+
+        for file in files.into_iter().filter(|f| f.is_file()) {
+            builder.add_ignore(file);
+        }
+    }
+
     let mut ovs = OverrideBuilder::new(&options.root);
     for file in options.custom_ignore.iter() {
-        ovs.add(&file).unwrap();
+        ovs.add(&file)?;
     }
 
-    builder.overrides(ovs.build().unwrap());
+    builder.overrides(ovs.build()?);
 
-    builder.build()
+    Ok(builder)
 }
 
 /// Add a node to `tree`, as a child of `node`, with `data` as the contents.
@@ -58,22 +162,39 @@ fn add_child_to_tree<T>(tree: &mut Arena<T>, node: NodeId, data: T) -> NodeId {
     new_node
 }
 
-fn path_to_string<P: AsRef<Path>>(p: &P) -> String {
-    match p.as_ref().file_name() {
-        Some(name) => name.to_str().unwrap_or("<node name non-UTF8"),
-        None => "<node name unknown>",
-    }.to_owned()
+/// Show `name` with control characters, backslashes and bytes that aren't
+/// valid UTF-8 escaped, e.g. as `\n`, `\\` and `\xff`.
+pub fn escape_name(name: &OsStr) -> String {
+    let mut s = String::new();
+    for chunk in name.as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c.is_control() || c == '\\' {
+                s.extend(c.escape_default());
+            } else {
+                s.push(c);
+            }
+        }
+        for b in chunk.invalid() {
+            s.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    s
+}
+
+fn path_to_name<P: AsRef<Path>>(p: &P) -> OsString {
+    p.as_ref()
+        .file_name()
+        .unwrap_or_else(|| OsStr::new("<node name unknown>"))
+        .to_owned()
 }
 
 fn de_to_fsentry(de: DirEntry) -> FsEntry {
-    let name = path_to_string(&de.path());
+    let name = path_to_name(&de.path());
     let ft = if de.path_is_symlink() {
-        let dest = match read_link(&de.path()) {
-            Ok(d) => path_to_string(&d),
-            Err(_) => "<error reading dest>".to_owned(),
-        };
-
-        FileType::LinkTo(dest)
+        FileType::LinkTo {
+            target: read_link(de.path()).unwrap_or_else(|_| PathBuf::from("<error reading dest>")),
+            kind: link_kind(de.path()),
+        }
     } else {
         match de.file_type() {
             Some(t) => if t.is_dir() {
@@ -85,44 +206,60 @@ fn de_to_fsentry(de: DirEntry) -> FsEntry {
         }
     };
 
-    FsEntry { ft, de, name }
+    let meta = FsMetadata::from_de(&de);
+
+    FsEntry {
+        ft,
+        de,
+        name,
+        meta,
+        total_size: 0,
+    }
 }
 
 fn root_to_fsentry<P: AsRef<Path>>(dir: &P, de: DirEntry) -> FsEntry {
     FsEntry {
         ft: FileType::Dir,
+        meta: FsMetadata::from_de(&de),
+        total_size: 0,
         de,
         name: if dir.as_ref() == OsStr::new(".") {
-            ".".to_owned()
+            OsString::from(".")
         } else {
-            let mut d = format!("{}", dir.as_ref().display());
-            if d.ends_with("/") {
-                d.pop();
-            }
-            d
+            let d = dir.as_ref().as_os_str().as_bytes();
+            OsStr::from_bytes(d.strip_suffix(b"/").unwrap_or(d)).to_owned()
         },
     }
 }
 
-enum DepthChange {
-    NextIsFirst,
-    Isnt,
-    Last(usize),
-}
-
-impl DepthChange {
-    fn for_depths(next: usize, curr: usize) -> Self {
-        if next < curr {
-            DepthChange::Last(curr - next)
-        } else if next > curr {
-            DepthChange::NextIsFirst
+/// What the link at `path` points to.
+fn link_kind(path: &Path) -> LinkKind {
+    match metadata(path) {
+        Ok(meta) => if meta.is_dir() {
+            LinkKind::Dir
         } else {
-            DepthChange::Isnt
-        }
+            LinkKind::File
+        },
+        Err(ref e) if e.raw_os_error() == Some(libc::ELOOP) => LinkKind::Loop,
+        Err(_) => LinkKind::Broken,
     }
 }
 
-fn is_or_points_to_dir(de: &DirEntry) -> bool {
+/// The absolute path that the link at `path`, to `target`, resolves to.
+///
+/// A link that can't be resolved, e.g. because it is broken, resolves to
+/// `target` relative to the directory containing the link.
+pub fn resolve_link(path: &Path, target: &Path) -> PathBuf {
+    canonicalize(path).unwrap_or_else(|_| {
+        let joined = match path.parent() {
+            Some(dir) => dir.join(target),
+            None => target.to_owned(),
+        };
+        absolute_path(&joined).unwrap_or(joined)
+    })
+}
+
+pub(crate) fn is_or_points_to_dir(de: &DirEntry) -> bool {
     match de.file_type() {
         Some(ft) => {
             if ft.is_dir() {
@@ -140,120 +277,712 @@ fn is_or_points_to_dir(de: &DirEntry) -> bool {
     }
 }
 
-fn determine_place_in_tree(
-    walk: &mut PutBack<Walk>,
-    fse: &mut FsEntry,
-    only_dirs: bool,
-) -> DepthChange {
-    while let Some(next) = walk.next() {
-        match next {
-            Ok(next) => {
-                if only_dirs && !is_or_points_to_dir(&next) {
-                    continue;
-                }
-
-                let nd = next.depth();
-                walk.put_back(Ok(next));
-                return DepthChange::for_depths(nd, fse.de.depth());
+/// The directory that `e` is a permission-denied error trying to recur into, if any.
+///
+/// This is fine, but we want to keep track of it.
+/// See https://github.com/BurntSushi/ripgrep/issue/953.
+fn restricted_dir(e: &ignore::Error) -> Option<&Path> {
+    match e {
+        ignore::Error::WithDepth { err, .. } => restricted_dir(err),
+        ignore::Error::WithPath { path, err } => match err.deref() {
+            ignore::Error::Io(inner) if inner.kind() == io::ErrorKind::PermissionDenied => {
+                Some(path)
             }
-            Err(ref e) => {
-                if let ignore::Error::WithPath { path, err } = e {
-                    if let ignore::Error::Io(inner) = err.deref() {
-                        if inner.kind() == io::ErrorKind::PermissionDenied && path == fse.de.path()
-                        {
-                            // A permission-denied error trying to recur into a subdirectory.
-                            // This is fine, but we want to keep track of it.
-                            // See https://github.com/BurntSushi/ripgrep/issue/953.
-                            fse.ft = FileType::RestrictedDir;
-                            continue;
-                        }
-                    }
-                }
+            _ => None,
+        },
+        _ => None,
+    }
+}
 
-                eprintln!("Unexpected error while building tree.\nDetails: {:?}", e);
-            }
+/// The link that `e` is an error following, if any, and what it points to.
+///
+/// Links that are followed, but loop or are broken, are reported as errors
+/// rather than entries.
+fn unfollowed_link(e: &ignore::Error) -> Option<(&Path, LinkKind)> {
+    match e {
+        ignore::Error::WithDepth { err, .. } => unfollowed_link(err),
+        ignore::Error::Loop { child, .. } => Some((child, LinkKind::Loop)),
+        ignore::Error::WithPath { path, .. }
+            if path
+                .symlink_metadata()
+                .map(|m| m.file_type().is_symlink())
+                .unwrap_or(false) =>
+        {
+            Some((path, link_kind(path)))
         }
+        _ => None,
     }
+}
 
-    DepthChange::Last(0)
+/// The error that kept the walk from reading the root of the tree.
+fn root_error(err: Option<ignore::Error>) -> ItreeError {
+    match err {
+        Some(e) => ItreeError::from(e),
+        None => unreachable!("Should always get a first walk entry"),
+    }
+}
+
+/// Walk the FS in parallel, collecting every entry and error.
+fn walk_parallel(builder: &WalkBuilder) -> (Vec<DirEntry>, Vec<ignore::Error>) {
+    let (sx, rx) = channel();
+    builder.build_parallel().run(|| {
+        let sx = sx.clone();
+        Box::new(move |res| {
+            sx.send(res).unwrap();
+            WalkState::Continue
+        })
+    });
+    drop(sx);
+
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for res in rx {
+        match res {
+            Ok(de) => entries.push(de),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    (entries, errors)
+}
+
+/// An error reading part of the FS, and the node it concerns: the entry it
+/// occurred at, or if that was left out of the tree, the nearest directory
+/// containing it.
+#[derive(Debug)]
+pub struct EntryError {
+    pub node: NodeId,
+    pub error: ItreeError,
+}
+
+/// What a walk added to the tree.
+#[derive(Debug, Default)]
+pub struct Added {
+    pub n_files: usize,
+    pub n_dirs: usize,
+    /// Directories whose children were left unread.
+    pub unread: Vec<NodeId>,
+    /// Every entry added, in the order they were placed.
+    pub placed: Vec<NodeId>,
+    /// Errors reading entries beneath the root of the walk.
+    pub errors: Vec<EntryError>,
 }
 
 /// Collect an Arena representation of the file system.
 ///
-/// Returns an Arena-tree, its root, and the number of files
-/// and directories in it.
+/// If `options.lazy_depth` is set, only that many levels are read. Directories
+/// whose children were left unread are returned, to be read later with `load_dir`.
+///
+/// Returns an Arena-tree, its root, and what was added beneath the root.
+/// Fails only if the root can't be read, or an ignore pattern can't be parsed.
 pub fn fs_to_tree<P: AsRef<Path>>(
     options: &FsOptions<P>,
-) -> (Arena<FsEntry>, NodeId, usize, usize) {
-    let mut walk = PutBack::new(get_walker(&options));
+) -> Result<(Arena<FsEntry>, NodeId, Added), ItreeError> {
+    let (depth, lazy) = depth_to_read(options, 0);
+    let walker = get_walker(options, options.root.as_ref(), depth)?;
+    let (mut entries, errors) = walk_parallel(&walker);
 
     let mut tree = Arena::<FsEntry>::new();
-    let root = match walk.next() {
-        Some(Ok(de)) => tree.new_node(root_to_fsentry(&options.root, de)),
-        Some(Err(ignore::Error::WithPath { path, err })) => {
-            if let ignore::Error::Io(e) = err.deref() {
-                if e.kind() == io::ErrorKind::NotFound {
-                    eprintln!("Path \"{}\" was not found", path.display());
-                } else {
-                    eprintln!(
-                        "An error occurred getting {}: {:?}",
-                        path.display(),
-                        e.kind()
-                    );
-                }
-            } else {
-                eprintln!("Could not get {}: {:?}", path.display(), err);
+    let root = match entries.iter().position(|de| de.depth() == 0) {
+        Some(i) => tree.new_node(root_to_fsentry(&options.root, entries.swap_remove(i))),
+        None => return Err(root_error(errors.into_iter().next())),
+    };
+
+    let added = add_to_tree(
+        &mut tree,
+        root,
+        entries,
+        errors,
+        options,
+        if lazy { depth } else { None },
+    );
+
+    Ok((tree, root, added))
+}
+
+/// Read the children of `dir`, a directory left unread by `fs_to_tree` or a
+/// previous `load_dir`, and add them to the tree.
+///
+/// `depth` is the depth of `dir` beneath the root of the tree. As many levels
+/// are read beneath it as `fs_to_tree` read beneath the root.
+///
+/// Returns what was added beneath `dir`, including the directories whose
+/// children were left unread in turn.
+pub fn load_dir<P: AsRef<Path>>(
+    options: &FsOptions<P>,
+    tree: &mut Arena<FsEntry>,
+    dir: NodeId,
+    depth: usize,
+) -> Result<Added, ItreeError> {
+    let (max_depth, lazy) = depth_to_read(options, depth);
+    let path = tree[dir].data.de.path().to_owned();
+    let (mut entries, errors) = walk_parallel(&get_walker(options, &path, max_depth)?);
+
+    // `dir` itself is already in the tree.
+    entries.retain(|de| de.depth() != 0);
+
+    Ok(add_to_tree(
+        tree,
+        dir,
+        entries,
+        errors,
+        options,
+        if lazy { max_depth } else { None },
+    ))
+}
+
+/// Read the entry at `path`, newly created in `dir`, and everything beneath
+/// it, and add them to the tree. Siblings are kept in `options.sort` order.
+///
+/// `depth` is the depth of `dir` beneath the root of the tree. The entry is
+/// left out if a walk of `dir` would leave it out, e.g. if it is ignored.
+///
+/// Returns what was added beneath `dir`, including the entry itself.
+pub fn load_entry<P: AsRef<Path>>(
+    options: &FsOptions<P>,
+    tree: &mut Arena<FsEntry>,
+    dir: NodeId,
+    depth: usize,
+    path: &Path,
+) -> Result<Added, ItreeError> {
+    let (max_depth, lazy) = depth_to_read(options, depth);
+    if max_depth == Some(0) {
+        return Ok(Added::default());
+    }
+
+    // The root of a walk is never ignored, so walk `dir`, keeping only the entry.
+    let dir_path = tree[dir].data.de.path().to_owned();
+    let target = path.to_owned();
+    let mut walker = get_walker(options, &dir_path, max_depth)?;
+    walker.filter_entry(move |de| de.depth() == 0 || de.path().starts_with(&target));
+    let (mut entries, errors) = walk_parallel(&walker);
+
+    // `dir` itself is already in the tree.
+    entries.retain(|de| de.depth() != 0);
+
+    Ok(add_to_tree(
+        tree,
+        dir,
+        entries,
+        errors,
+        options,
+        if lazy { max_depth } else { None },
+    ))
+}
+
+/// Read the entry at `path` by itself, without anything beneath it, e.g. to
+/// add to a tree with `Tree::insert_child`.
+pub fn read_entry(path: &Path) -> Result<FsEntry, ItreeError> {
+    match WalkBuilder::new(path).max_depth(Some(0)).build().next() {
+        Some(Ok(de)) => Ok(de_to_fsentry(de)),
+        Some(Err(e)) => Err(ItreeError::from(e)),
+        None => unreachable!("Should always get a first walk entry"),
+    }
+}
+
+/// Read the entries for the links in `dir` themselves, rather than what they
+/// point to, by path.
+///
+/// The root of a walk is always followed, so this walks `dir` for them.
+fn read_link_entries(dir: &Path) -> HashMap<PathBuf, DirEntry> {
+    WalkBuilder::new(dir)
+        .standard_filters(false)
+        .max_depth(Some(1))
+        .build()
+        .filter_map(|res| res.ok())
+        .filter(|de| de.depth() == 1 && de.path_is_symlink())
+        .map(|de| (de.path().to_owned(), de))
+        .collect()
+}
+
+/// Whether `fse` is counted as a directory: it is one, or is a link to one,
+/// even if the link loops.
+pub(crate) fn counts_as_dir(fse: &FsEntry) -> bool {
+    match fse.ft {
+        FileType::Dir | FileType::RestrictedDir | FileType::MountPoint => true,
+        FileType::LinkTo { kind, .. } => kind == LinkKind::Dir || kind == LinkKind::Loop,
+        FileType::File | FileType::Stdin => false,
+    }
+}
+
+/// How many levels to read beneath a directory `depth` levels below the root,
+/// and whether that stops short of `options.max_depth` because of `options.lazy_depth`.
+fn depth_to_read<P: AsRef<Path>>(options: &FsOptions<P>, depth: usize) -> (Option<usize>, bool) {
+    let remaining = options.max_depth.map(|d| d.saturating_sub(depth));
+    match (options.lazy_depth, remaining) {
+        (Some(l), Some(r)) if l < r => (Some(l), true),
+        (Some(l), None) => (Some(l), true),
+        _ => (remaining, false),
+    }
+}
+
+/// Add walked entries beneath `root` in the tree, each under the entry for its
+/// parent directory, with siblings in `options.sort` order.
+///
+/// If `unread_depth` is given, directories at that depth are returned as unread.
+fn add_to_tree<P: AsRef<Path>>(
+    tree: &mut Arena<FsEntry>,
+    root: NodeId,
+    mut entries: Vec<DirEntry>,
+    errors: Vec<ignore::Error>,
+    options: &FsOptions<P>,
+    unread_depth: Option<usize>,
+) -> Added {
+    let mut placer = Placer::new(tree, root, options, unread_depth);
+
+    // Every directory has to be placed before its contents. Paths compare
+    // component by component, so sorting by them does that. It also keeps
+    // `SortBy::None` from depending on the order the walk's threads ran in.
+    entries.sort_by(|a, b| a.path().cmp(b.path()));
+
+    let mut parents = HashSet::new();
+    for de in entries {
+        if let Some(parent) = placer.place(tree, de) {
+            parents.insert(parent);
+        }
+    }
+
+    // Errors are placed last, so that they can be recorded against entries.
+    for e in errors {
+        if let Some(parent) = placer.error(tree, e) {
+            parents.insert(parent);
+        }
+    }
+
+    for parent in parents {
+        sort_children(tree, parent, options.sort);
+    }
+
+    placer.take_added()
+}
+
+/// Places walked entries beneath a directory in the tree, each under the
+/// entry for its parent directory.
+#[derive(Debug)]
+struct Placer {
+    root: NodeId,
+    nodes: HashMap<PathBuf, NodeId>,
+    only_dirs: bool,
+    follow_links: bool,
+    sort: SortOrder,
+    /// The device of the file system the walk stays on, if it does.
+    device: Option<u64>,
+    /// Directories at this depth are unread.
+    unread_depth: Option<usize>,
+    /// The links in each directory that has had a link the walk couldn't
+    /// follow, read for themselves, so that it is only read once.
+    links: HashMap<PathBuf, HashMap<PathBuf, DirEntry>>,
+    added: Added,
+}
+
+impl Placer {
+    fn new<P: AsRef<Path>>(
+        tree: &Arena<FsEntry>,
+        root: NodeId,
+        options: &FsOptions<P>,
+        unread_depth: Option<usize>,
+    ) -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(tree[root].data.de.path().to_owned(), root);
+
+        Self {
+            root,
+            nodes,
+            only_dirs: options.only_dirs,
+            follow_links: options.follow_links,
+            sort: options.sort,
+            device: tree[root]
+                .data
+                .meta
+                .filter(|_| options.same_file_system)
+                .map(|m| m.dev),
+            unread_depth,
+            links: HashMap::new(),
+            added: Added::default(),
+        }
+    }
+
+    /// Record an error from the walk against the nearest entry to it that
+    /// has been placed, marking the directory it was trying to read, if any,
+    /// as restricted. A link it couldn't follow is placed, marked as broken
+    /// or looping, and the error recorded against it.
+    ///
+    /// Returns the parent of the link placed, if any.
+    fn error(&mut self, tree: &mut Arena<FsEntry>, e: ignore::Error) -> Option<NodeId> {
+        if let Some(&node) = restricted_dir(&e).and_then(|p| self.nodes.get(p)) {
+            tree[node].data.ft = FileType::RestrictedDir;
+        }
+        let parent = match unfollowed_link(&e) {
+            Some((path, kind)) => {
+                let path = path.to_owned();
+                self.place_link(tree, &path, kind)
             }
+            None => None,
+        };
+
+        let error = ItreeError::from(e);
+        let node = error
+            .path()
+            .and_then(|p| p.ancestors().filter_map(|a| self.nodes.get(a)).next())
+            .cloned()
+            .unwrap_or(self.root);
+
+        self.added.errors.push(EntryError { node, error });
+        parent
+    }
+
+    /// Add the link at `path`, which the walk couldn't follow, to the tree,
+    /// pointing to `kind`.
+    ///
+    /// Returns the parent, or `None` if the link was left out.
+    fn place_link(
+        &mut self,
+        tree: &mut Arena<FsEntry>,
+        path: &Path,
+        kind: LinkKind,
+    ) -> Option<NodeId> {
+        let parent = match path.parent().and_then(|p| self.nodes.get(p)) {
+            Some(&p) => p,
+            None => return None,
+        };
+        let mut fse = match self.link_entry(path) {
+            Some(de) => de_to_fsentry(de),
+            None => return None,
+        };
+        if let FileType::LinkTo { kind: ref mut k, .. } = fse.ft {
+            *k = kind;
+        }
+
+        if counts_as_dir(&fse) {
+            self.added.n_dirs += 1;
+        } else if self.only_dirs {
+            return None;
+        } else {
+            self.added.n_files += 1;
+        }
+
+        let node = add_child_to_tree(tree, parent, fse);
+        self.added.placed.push(node);
+        self.nodes.insert(path.to_owned(), node);
 
-            ::std::process::exit(1);
+        Some(parent)
+    }
+
+    /// The entry for the link at `path` itself, reading the directory it is in
+    /// if that hasn't been read for links already.
+    fn link_entry(&mut self, path: &Path) -> Option<DirEntry> {
+        let dir = path.parent()?;
+        if !self.links.get(dir).is_some_and(|links| links.contains_key(path)) {
+            self.links.insert(dir.to_owned(), read_link_entries(dir));
         }
-        Some(Err(_)) => {
-            eprintln!("An unexpected error occurred getting the root!");
-            ::std::process::exit(2);
+        self.links.get_mut(dir)?.remove(path)
+    }
+
+    /// Add `de` to the tree as the last child of its parent, which must
+    /// already have been placed.
+    ///
+    /// Returns the parent, or `None` if `de` was left out.
+    fn place(&mut self, tree: &mut Arena<FsEntry>, de: DirEntry) -> Option<NodeId> {
+        let parent = match de.path().parent().and_then(|p| self.nodes.get(p)) {
+            Some(&p) => p,
+            None => return None,
+        };
+
+        let path = de.path().to_owned();
+        let mut fse = de_to_fsentry(de);
+        if counts_as_dir(&fse) {
+            self.added.n_dirs += 1;
+        } else if self.only_dirs {
+            return None;
+        } else {
+            self.added.n_files += 1;
         }
-        None => {
-            unreachable!("Should always get a first walk entry");
+
+        // Entries that couldn't be stat-ed can't be told apart, so stay as they are.
+        let other_fs = match (self.device, fse.meta) {
+            (Some(dev), Some(m)) => m.dev != dev,
+            _ => false,
+        };
+        if fse.ft == FileType::Dir && other_fs {
+            fse.ft = FileType::MountPoint;
+        }
+        let is_unread = Some(fse.de.depth()) == self.unread_depth
+            && (fse.ft == FileType::Dir || (self.follow_links && is_or_points_to_dir(&fse.de)));
+
+        let node = add_child_to_tree(tree, parent, fse);
+        self.added.placed.push(node);
+        if is_unread {
+            self.added.unread.push(node);
         }
+        self.nodes.insert(path, node);
+
+        Some(parent)
+    }
+
+    /// What has been placed, and the errors seen, since the last call.
+    fn take_added(&mut self) -> Added {
+        mem::take(&mut self.added)
+    }
+}
+
+/// Sort the children of `node` in `sort` order.
+pub fn sort_children(tree: &mut Arena<FsEntry>, node: NodeId, sort: SortOrder) {
+    let mut children = node.children(tree).collect::<Vec<NodeId>>();
+    children.sort_by(|&a, &b| compare_entries(sort, &tree[a].data, &tree[b].data));
+
+    for child in children {
+        child.detach(tree);
+        node.append(child, tree);
+    }
+}
+
+/// Compare two entries in the same directory, to put them in `sort` order.
+///
+/// Entries that compare equal, e.g. any two when sorting by `SortBy::None`,
+/// keep their order in a stable sort.
+pub fn compare_entries(sort: SortOrder, a: &FsEntry, b: &FsEntry) -> cmp::Ordering {
+    let is_dir = |e: &FsEntry| e.de.file_type().is_some_and(|t| t.is_dir());
+    let first = if sort.dirs_first {
+        is_dir(b).cmp(&is_dir(a))
+    } else {
+        cmp::Ordering::Equal
     };
 
-    let mut n_files = 0;
-    let mut n_dirs = 0;
-    let mut curr = root;
-    while let Some(res) = walk.next() {
-        let mut fse = match res {
-            Ok(de) => {
-                if is_or_points_to_dir(&de) {
-                    n_dirs += 1;
-                } else if options.only_dirs {
-                    continue;
-                } else {
-                    n_files += 1;
-                }
+    // Entries that couldn't be stat-ed count as smaller and older than the rest.
+    let (ma, mb) = (a.meta, b.meta);
+    let by_name = || a.name.cmp(&b.name);
+    let ord = match sort.by {
+        SortBy::Name => by_name(),
+        SortBy::Natural => natural_cmp(&a.name.to_string_lossy(), &b.name.to_string_lossy())
+            .then_with(by_name),
+        SortBy::Size => mb.map(|m| m.size)
+            .cmp(&ma.map(|m| m.size))
+            .then_with(by_name),
+        SortBy::Mtime => mb.map(|m| m.mtime)
+            .cmp(&ma.map(|m| m.mtime))
+            .then_with(by_name),
+        SortBy::Ctime => mb.map(|m| m.ctime)
+            .cmp(&ma.map(|m| m.ctime))
+            .then_with(by_name),
+        SortBy::Ext => Path::new(&a.name)
+            .extension()
+            .cmp(&Path::new(&b.name).extension())
+            .then_with(by_name),
+        SortBy::None => cmp::Ordering::Equal,
+    };
+
+    first.then(if sort.reverse { ord.reverse() } else { ord })
+}
 
-                de_to_fsentry(de)
+/// Compare names ignoring case, and comparing runs of digits by their value.
+fn natural_cmp(a: &str, b: &str) -> cmp::Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        let ord = match (a.peek().cloned(), b.peek().cloned()) {
+            (None, None) => return cmp::Ordering::Equal,
+            (None, Some(_)) => return cmp::Ordering::Less,
+            (Some(_), None) => return cmp::Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (m, n) = (take_number(&mut a), take_number(&mut b));
+                m.len().cmp(&n.len()).then_with(|| m.cmp(&n))
             }
-            Err(_) => {
-                panic!("This error should have been handled in `determine_place_in_tree` in the previous iteration.");
+            (Some(x), Some(y)) => {
+                a.next();
+                b.next();
+                x.to_lowercase().cmp(y.to_lowercase())
             }
         };
 
-        match determine_place_in_tree(&mut walk, &mut fse, options.only_dirs) {
-            DepthChange::NextIsFirst => {
-                curr = add_child_to_tree(&mut tree, curr, fse);
-            }
-            DepthChange::Isnt => {
-                add_child_to_tree(&mut tree, curr, fse);
-            }
-            DepthChange::Last(d) => {
-                add_child_to_tree(&mut tree, curr, fse);
-                for _ in 0..d {
-                    curr = tree[curr].parent().expect("The node should have a parent");
+        if ord != cmp::Ordering::Equal {
+            return ord;
+        }
+    }
+}
+
+/// Take a run of digits from the front of `chars`, without leading zeros.
+fn take_number<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> String {
+    let mut digits = String::new();
+    while let Some(&c) = chars.peek() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        digits.push(c);
+        chars.next();
+    }
+
+    digits.trim_start_matches('0').to_owned()
+}
+
+/// The longest to spend adding entries to the tree in one `Scan::poll`, so
+/// that a fast walk doesn't keep the UI from responding.
+const MAX_POLL_MS: u64 = 50;
+
+/// A walk of the FS running on a background thread, whose entries are added
+/// to the tree as they are polled.
+#[derive(Debug)]
+pub struct Scan {
+    rx: Receiver<Result<DirEntry, ignore::Error>>,
+    stop: Arc<AtomicBool>,
+    placer: Placer,
+    finished: bool,
+}
+
+/// Start walking the FS on a background thread.
+///
+/// Only waits to read the root, which is returned in an Arena-tree of its own.
+/// The rest of the tree is added to it by polling the returned `Scan`.
+///
+/// Fails only if the root can't be read, or an ignore pattern can't be parsed.
+pub fn start_scan<P: AsRef<Path>>(
+    options: &FsOptions<P>,
+) -> Result<(Arena<FsEntry>, NodeId, Scan), ItreeError> {
+    let (depth, lazy) = depth_to_read(options, 0);
+    let walker = get_walker(options, options.root.as_ref(), depth)?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let (sx, rx) = channel();
+
+    let walk_stop = stop.clone();
+    thread::spawn(move || {
+        walker.build_parallel().run(|| {
+            let sx = sx.clone();
+            let stop = walk_stop.clone();
+            Box::new(move |res| {
+                if stop.load(Ordering::SeqCst) || sx.send(res).is_err() {
+                    WalkState::Quit
+                } else {
+                    WalkState::Continue
+                }
+            })
+        });
+    });
+
+    // The walk visits a directory before reading it, so the root comes first,
+    // and every other directory comes before its contents.
+    let mut tree = Arena::<FsEntry>::new();
+    let mut errors = Vec::new();
+    let root = loop {
+        match rx.recv() {
+            Ok(Ok(de)) => break tree.new_node(root_to_fsentry(&options.root, de)),
+            Ok(Err(e)) => errors.push(e),
+            Err(_) => return Err(root_error(errors.into_iter().next())),
+        }
+    };
+
+    // Errors seen before the root, e.g. reading ignore files above it, are
+    // recorded against it, to be returned by the first poll.
+    let mut placer = Placer::new(&tree, root, options, if lazy { depth } else { None });
+    for e in errors {
+        placer.error(&mut tree, e);
+    }
+    let scan = Scan {
+        rx,
+        stop,
+        placer,
+        finished: false,
+    };
+
+    Ok((tree, root, scan))
+}
+
+impl Scan {
+    /// Add the entries read since the last poll to `tree`, without waiting
+    /// for any more. Siblings are kept in sort order.
+    ///
+    /// If entries are read faster than they can be added, the rest are left
+    /// for the next poll.
+    ///
+    /// Returns what was added to the tree.
+    pub fn poll(&mut self, tree: &mut Arena<FsEntry>) -> Added {
+        let start = Instant::now();
+        let mut parents = HashSet::new();
+        while start.elapsed() < Duration::from_millis(MAX_POLL_MS) {
+            match self.rx.try_recv() {
+                Ok(Ok(de)) => {
+                    if let Some(parent) = self.placer.place(tree, de) {
+                        parents.insert(parent);
+                    }
+                }
+                Ok(Err(e)) => {
+                    if let Some(parent) = self.placer.error(tree, e) {
+                        parents.insert(parent);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.finished = true;
+                    break;
                 }
             }
         }
+
+        for parent in parents {
+            sort_children(tree, parent, self.placer.sort);
+        }
+
+        self.placer.take_added()
     }
 
-    (tree, root, n_files, n_dirs)
+    /// Order the entries placed from now on by `sort`.
+    pub fn set_sort(&mut self, sort: SortOrder) {
+        self.placer.sort = sort;
+    }
+
+    /// Whether the walk has finished, and every entry it read has been polled.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Stop the walk, and wait for its threads to finish.
+    ///
+    /// Entries read but not yet polled are dropped.
+    pub fn cancel(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        while self.rx.recv().is_ok() {}
+        self.finished = true;
+    }
+}
+
+/// Make `path` absolute, resolving links in its parent directories but not in the
+/// path's final component.
+pub fn absolute_path(path: &Path) -> io::Result<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+
+            Ok(canonicalize(parent)?.join(name))
+        }
+        _ => canonicalize(path),
+    }
+}
+
+/// Set the `total_size` of every entry beneath `root`, summing subtrees into directories.
+///
+/// Returns the total size of the tree.
+pub fn aggregate_sizes(tree: &mut Arena<FsEntry>, root: NodeId) -> u64 {
+    let nodes = root.descendants(tree).collect::<Vec<NodeId>>();
+
+    for &nid in nodes.iter() {
+        let own = tree[nid].data.meta.map(|m| m.size).unwrap_or(0);
+        tree[nid].data.total_size = own;
+    }
+
+    // Descendants are in pre-order, so every child is visited before its parent.
+    for &nid in nodes.iter().rev() {
+        if nid == root {
+            continue;
+        }
+
+        let size = tree[nid].data.total_size;
+        let parent = tree[nid].parent().expect("The node should have a parent");
+        tree[parent].data.total_size += size;
+    }
+
+    tree[root].data.total_size
 }
 
 #[cfg(test)]
@@ -271,10 +1000,17 @@ mod tests {
     }
 
     fn test_tree(dir: &PathBuf) -> (Arena<FsEntry>, NodeId) {
-        let (tree, root, _, _) = fs_to_tree(&FsOptions::new(dir));
+        let (tree, root, _) = fs_to_tree(&FsOptions::new(dir)).unwrap();
         (tree, root)
     }
 
+    #[test]
+    fn test_escape_name() {
+        assert_eq!("plain é", escape_name(OsStr::new("plain é")));
+        assert_eq!("a\\tb\\\\c", escape_name(OsStr::new("a\tb\\c")));
+        assert_eq!("\\xff\\xfeok", escape_name(OsStr::from_bytes(b"\xff\xfeok")));
+    }
+
     #[test]
     fn test_collect_fs_abs_path() {
         let (tree, root) = test_tree(&abs_test_dir("simple"));
@@ -282,12 +1018,12 @@ mod tests {
         let mut curr = ::std::env::current_dir().unwrap();
         curr.push("resources/test/simple");
         assert_eq!(
-            format!("{}", curr.as_path().display()),
+            curr.as_os_str(),
             tree[root].data.name
         );
 
         let children = root.children(&tree)
-            .map(|nid| tree[nid].data.name.as_str())
+            .map(|nid| tree[nid].data.name.to_str().unwrap())
             .collect::<Vec<&str>>();
 
         assert!(children.contains(&"myfile"));
@@ -300,7 +1036,7 @@ mod tests {
         assert_eq!(tree[root].data.name, "resources/test/simple");
 
         let children = root.children(&tree)
-            .map(|nid| tree[nid].data.name.as_str())
+            .map(|nid| tree[nid].data.name.to_str().unwrap())
             .collect::<Vec<&str>>();
 
         assert!(children.contains(&"myfile"));
@@ -313,14 +1049,14 @@ mod tests {
         assert_eq!(tree[root].data.name, "resources/test/one_dir");
 
         let children = root.children(&tree)
-            .map(|nid| tree[nid].data.name.as_str())
+            .map(|nid| tree[nid].data.name.to_str().unwrap())
             .collect::<Vec<&str>>();
 
         assert!(children.contains(&"mydir"));
         assert!(children.contains(&"myotherfile"));
 
         let dir_node = root.children(&tree)
-            .filter(|nid| tree[*nid].data.name.as_str() == "mydir")
+            .filter(|nid| tree[*nid].data.name.to_str().unwrap() == "mydir")
             .next()
             .unwrap();
 
@@ -329,4 +1065,246 @@ mod tests {
             "myfile",
         );
     }
+
+    #[test]
+    fn test_collect_fs_sorted() {
+        let (tree, root) = test_tree(&test_dir(""));
+
+        let names = root.descendants(&tree)
+            .skip(1)
+            .map(|nid| tree[nid].data.de.path().strip_prefix(test_dir("")).unwrap().to_owned())
+            .collect::<Vec<PathBuf>>();
+
+        let expected = [
+            "link",
+            "link/dest",
+            "link/source",
+            "one_dir",
+            "one_dir/mydir",
+            "one_dir/mydir/myfile",
+            "one_dir/myotherfile",
+            "simple",
+            "simple/myfile",
+            "simple/myotherfile",
+        ];
+        assert_eq!(
+            expected.iter().map(PathBuf::from).collect::<Vec<PathBuf>>(),
+            names
+        );
+    }
+
+    #[test]
+    fn test_collect_fs_sort_orders() {
+        use options::SortBy;
+        use std::fs::{create_dir, File};
+        use std::io::Write;
+        use util::TempDir;
+
+        let dir = TempDir::new("sort");
+        for (name, len) in [("b.txt", 3), ("a.rs", 1), ("Makefile", 2), ("v10", 5), ("v9", 4)].iter() {
+            File::create(dir.join(name))
+                .unwrap()
+                .write_all(&vec![0; *len])
+                .unwrap();
+        }
+        create_dir(dir.join("d")).unwrap();
+
+        let names = |opts: &FsOptions<&TempDir>| {
+            let (tree, root, _) = fs_to_tree(opts).unwrap();
+            root.children(&tree)
+                .map(|n| tree[n].data.name.to_string_lossy().into_owned())
+                .collect::<Vec<String>>()
+        };
+
+        let mut opts = FsOptions::new(&dir);
+        let name = names(&opts);
+        let natural = names(opts.sort_by(SortBy::Natural));
+        let natural_dirs_first = names(opts.dirs_first(true));
+        let reverse_dirs_first = names(opts.reverse(true));
+        let ext = names(opts.sort_by(SortBy::Ext).reverse(false).dirs_first(false));
+        let mut size = names(opts.sort_by(SortBy::Size));
+
+        assert_eq!(vec!["Makefile", "a.rs", "b.txt", "d", "v10", "v9"], name);
+        assert_eq!(vec!["a.rs", "b.txt", "d", "Makefile", "v9", "v10"], natural);
+        assert_eq!(vec!["d", "a.rs", "b.txt", "Makefile", "v9", "v10"], natural_dirs_first);
+        assert_eq!(vec!["d", "v10", "v9", "Makefile", "b.txt", "a.rs"], reverse_dirs_first);
+        assert_eq!(vec!["Makefile", "d", "v10", "v9", "a.rs", "b.txt"], ext);
+        // The directory's size depends on the FS.
+        size.retain(|n| n != "d");
+        assert_eq!(vec!["v10", "v9", "b.txt", "Makefile", "a.rs"], size);
+    }
+
+    #[test]
+    fn test_collect_fs_unsorted_deterministic() {
+        use options::SortBy;
+
+        let mut opts = FsOptions::new(test_dir(""));
+        opts.sort_by(SortBy::None);
+
+        let draw = |tree: &Arena<FsEntry>, root: NodeId| {
+            root.descendants(tree)
+                .map(|n| {
+                    let depth = n.ancestors(tree).count() - 1;
+                    format!("{}{}\n", "  ".repeat(depth), tree[n].data.name.to_string_lossy())
+                })
+                .collect::<String>()
+        };
+
+        let (tree, root, _) = fs_to_tree(&opts).unwrap();
+        let parallel = draw(&tree, root);
+
+        let mut entries = get_walker(&opts, &test_dir(""), None)
+            .unwrap()
+            .build()
+            .collect::<Result<Vec<DirEntry>, ignore::Error>>()
+            .unwrap();
+        let mut tree = Arena::<FsEntry>::new();
+        let root = tree.new_node(root_to_fsentry(&opts.root, entries.remove(0)));
+        add_to_tree(&mut tree, root, entries, Vec::new(), &opts, None);
+        let sequential = draw(&tree, root);
+
+        assert_eq!(sequential, parallel);
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut names = vec!["v10", "file01", "B", "v9", "a", "file1", "v010"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(vec!["a", "B", "file01", "file1", "v9", "v10", "v010"], names);
+    }
+
+    #[test]
+    fn test_collect_fs_same_file_system() {
+        // /proc is a file system of its own, wherever it is mounted.
+        let root_dev = metadata("/").unwrap().dev();
+        match metadata("/proc") {
+            Ok(m) if m.is_dir() && m.dev() != root_dev => {}
+            _ => return,
+        }
+
+        let mut opts = FsOptions::new("/");
+        opts.max_depth(Some(1));
+        let proc_is_mount_point = |opts: &FsOptions<&str>| {
+            let (tree, root, _) = fs_to_tree(opts).unwrap();
+            let node = root.children(&tree)
+                .find(|&n| tree[n].data.name == "proc")
+                .unwrap();
+            tree[node].data.ft == FileType::MountPoint
+        };
+
+        assert!(!proc_is_mount_point(&opts));
+        assert!(proc_is_mount_point(opts.same_file_system(true)));
+    }
+
+    #[test]
+    fn test_collect_fs_errors() {
+        match fs_to_tree(&FsOptions::new(test_dir("nonexistent"))) {
+            Err(ItreeError::NotFound(path)) => assert_eq!(test_dir("nonexistent"), path),
+            res => panic!("Unexpected result {:?}", res.map(|_| ())),
+        }
+
+        let mut opts = FsOptions::new(test_dir("simple"));
+        opts.add_custom_ignore("!my[file");
+        match fs_to_tree(&opts) {
+            Err(ItreeError::BadIgnorePattern { pattern, .. }) => assert_eq!("!my[file", pattern),
+            res => panic!("Unexpected result {:?}", res.map(|_| ())),
+        }
+        match start_scan(&opts) {
+            Err(ItreeError::BadIgnorePattern { .. }) => {}
+            res => panic!("Unexpected result {:?}", res.map(|_| ())),
+        }
+
+        let (_, _, added) = fs_to_tree(&FsOptions::new(test_dir("one_dir"))).unwrap();
+        assert!(added.errors.is_empty());
+    }
+
+    #[test]
+    fn test_scan_errors_before_root() {
+        use std::fs::{create_dir_all, File};
+        use std::io::Write;
+        use util::TempDir;
+
+        // Ignore files above the root are read before it is visited.
+        let dir = TempDir::new("scan-errors");
+        create_dir_all(dir.join("root")).unwrap();
+        File::create(dir.join(".ignore"))
+            .unwrap()
+            .write_all(b"a{b\n")
+            .unwrap();
+
+        let mut opts = FsOptions::new(dir.join("root"));
+        opts.no_ignore(false);
+
+        let (mut tree, root, mut scan) = start_scan(&opts).unwrap();
+        let mut errors = Vec::new();
+        while !scan.is_finished() {
+            errors.extend(scan.poll(&mut tree).errors);
+            ::std::thread::sleep(::std::time::Duration::from_millis(1));
+        }
+        let (_, _, added) = fs_to_tree(&opts).unwrap();
+
+        assert_eq!(1, errors.len());
+        assert_eq!(root, errors[0].node);
+        assert_eq!(added.errors.len(), errors.len());
+    }
+
+    #[test]
+    fn test_collect_fs_metadata() {
+        let (tree, root) = test_tree(&test_dir("one_dir"));
+
+        let root_meta = tree[root].data.meta.expect("root should have metadata");
+        assert!(root_meta.nlink >= 1);
+
+        for nid in root.children(&tree) {
+            let entry = &tree[nid].data;
+            let meta = entry.meta.expect("entry should have metadata");
+            let expected = ::std::fs::metadata(entry.de.path()).unwrap();
+
+            assert_eq!(expected.len(), meta.size);
+            assert_eq!(expected.ino(), meta.inode);
+            assert_eq!(expected.mode(), meta.mode);
+            assert_eq!(expected.mtime(), meta.mtime);
+        }
+    }
+
+    #[test]
+    fn test_absolute_path() {
+        let base = canonicalize(test_dir("")).unwrap();
+
+        // The link itself, not its destination.
+        assert_eq!(
+            base.join("link/dest"),
+            absolute_path(&test_dir("link").join("dest")).unwrap()
+        );
+        assert_eq!(
+            base,
+            absolute_path(&test_dir("simple").join("..")).unwrap()
+        );
+        assert_eq!(
+            base.join("simple"),
+            absolute_path(&abs_test_dir("simple")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_aggregate_sizes() {
+        let (mut tree, root) = test_tree(&test_dir("one_dir"));
+        let total = aggregate_sizes(&mut tree, root);
+
+        let own_size = |nid: NodeId| tree[nid].data.meta.unwrap().size;
+
+        let dir_node = root.children(&tree)
+            .filter(|nid| tree[*nid].data.name.to_str().unwrap() == "mydir")
+            .next()
+            .unwrap();
+        let file_node = dir_node.children(&tree).next().unwrap();
+
+        assert_eq!(own_size(file_node), tree[file_node].data.total_size);
+        assert_eq!(
+            own_size(dir_node) + own_size(file_node),
+            tree[dir_node].data.total_size
+        );
+        assert_eq!(root.descendants(&tree).map(own_size).sum::<u64>(), total);
+        assert_eq!(total, tree[root].data.total_size);
+    }
 }
@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+use libc;
+use termion::event::{self, Key};
+
+/// How long to wait for input before checking for a resize or tick again.
+///
+/// A resize interrupts the wait, so this only matters for it if the signal
+/// arrives just before waiting begins.
+const POLL_TIMEOUT_MS: libc::c_int = 100;
+
+static RESIZED: AtomicBool = AtomicBool::new(false);
+static HANDLE_RESIZE: Once = Once::new();
+
+extern "C" fn on_resize(_: libc::c_int) {
+    RESIZED.store(true, Ordering::SeqCst);
+}
+
+/// Something the interactive UI should respond to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event {
+    Key(Key),
+    /// The terminal was resized.
+    Resize,
+    /// The interval set with `Input::set_tick` passed.
+    Tick,
+}
+
+/// A source of key presses from stdin, merged with terminal resizes.
+///
+/// Unlike `termion::input::TermRead::keys`, waiting for a key can be
+/// interrupted by a resize, so that the UI can be redrawn at once.
+pub struct Input {
+    pending: VecDeque<Key>,
+    tick: Option<Duration>,
+    last_tick: Instant,
+}
+
+impl Input {
+    /// Start listening for resizes, and prepare to read keys from stdin.
+    pub fn new() -> Self {
+        HANDLE_RESIZE.call_once(|| unsafe {
+            let mut action: libc::sigaction = ::std::mem::zeroed();
+            action.sa_sigaction = on_resize as extern "C" fn(libc::c_int) as usize;
+            // No SA_RESTART, so that a resize interrupts poll().
+            action.sa_flags = 0;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGWINCH, &action, ::std::ptr::null_mut());
+        });
+
+        Self {
+            pending: VecDeque::new(),
+            tick: None,
+            last_tick: Instant::now(),
+        }
+    }
+
+    /// Set an interval at which to send `Event::Tick` while waiting for keys.
+    /// `None` stops the ticks.
+    pub fn set_tick(&mut self, tick: Option<Duration>) {
+        self.tick = tick;
+        self.last_tick = Instant::now();
+    }
+
+    fn tick_due(&self) -> bool {
+        match self.tick {
+            Some(tick) => self.last_tick.elapsed() >= tick,
+            None => false,
+        }
+    }
+
+    /// How long to wait for input, in milliseconds, without missing a tick.
+    fn poll_timeout(&self) -> libc::c_int {
+        match self.tick {
+            Some(tick) => {
+                let left = tick
+                    .checked_sub(self.last_tick.elapsed())
+                    .unwrap_or(Duration::from_millis(0));
+                let left_ms = left.as_secs() * 1000 + u64::from(left.subsec_millis());
+                POLL_TIMEOUT_MS.min(left_ms as libc::c_int)
+            }
+            None => POLL_TIMEOUT_MS,
+        }
+    }
+
+    /// Wait for the next event.
+    ///
+    /// Returns `None` once stdin is closed.
+    pub fn next_event(&mut self) -> io::Result<Option<Event>> {
+        loop {
+            if RESIZED.swap(false, Ordering::SeqCst) {
+                return Ok(Some(Event::Resize));
+            }
+            if let Some(key) = self.pending.pop_front() {
+                return Ok(Some(Event::Key(key)));
+            }
+
+            let mut fd = libc::pollfd {
+                fd: libc::STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut fd, 1, self.poll_timeout()) } < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            // Only tick once there are no keys waiting, so that keys are
+            // handled even if each tick takes longer than the interval.
+            if fd.revents == 0 {
+                if self.tick_due() {
+                    self.last_tick = Instant::now();
+                    return Ok(Some(Event::Tick));
+                }
+                continue;
+            }
+
+            let mut buf = [0u8; 1024];
+            let n = unsafe {
+                libc::read(
+                    libc::STDIN_FILENO,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            match n {
+                0 => return Ok(None),
+                n if n < 0 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                n => self.pending.extend(parse_keys(&buf[..n as usize])),
+            }
+        }
+    }
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parse the keys in a chunk of bytes read from the terminal.
+///
+/// An escape byte that ends the chunk is taken to be the Escape key itself,
+/// rather than the start of an escape sequence. Unrecognized sequences and
+/// non-key events are skipped.
+fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut iter = bytes.iter().map(|&b| Ok(b)).peekable();
+
+    while let Some(Ok(b)) = iter.next() {
+        if b == b'\x1B' && iter.peek().is_none() {
+            keys.push(Key::Esc);
+            continue;
+        }
+
+        if let Ok(event::Event::Key(key)) = event::parse_event(b, &mut iter) {
+            keys.push(key);
+        }
+    }
+
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(
+            vec![Key::Char('j'), Key::Up, Key::Char('é'), Key::Ctrl('c')],
+            parse_keys(b"j\x1B[A\xC3\xA9\x03")
+        );
+        assert_eq!(vec![Key::Char('q'), Key::Esc], parse_keys(b"q\x1B"));
+        assert_eq!(vec![Key::Alt('f')], parse_keys(b"\x1Bf"));
+    }
+}
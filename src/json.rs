@@ -0,0 +1,217 @@
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::os::unix::ffi::OsStrExt;
+
+use indextree::NodeId;
+
+use fs::{FileType, FsEntry, FsMetadata, LinkKind};
+use tree::Tree;
+
+/// Serialize a tree as JSON, in the style of `tree -J`.
+///
+/// The output is an array holding the root entry, with entries nested under
+/// their directories' `contents`, followed by a report of the summary counts.
+/// Entries hidden by an active filter are left out.
+pub fn tree_to_json(tree: &Tree) -> String {
+    let mut out = String::from("[\n");
+    write_entry(&mut out, tree, tree.root, 1);
+    out.push_str(",\n");
+
+    let (n_files, n_dirs) = tree.counts();
+    write!(
+        out,
+        "  {{\"type\":\"report\",\"directories\":{},\"files\":{}}}\n]\n",
+        n_dirs, n_files
+    ).unwrap();
+
+    out
+}
+
+fn write_entry(out: &mut String, tree: &Tree, node: NodeId, depth: usize) {
+    let entry = &tree.tree[node].data;
+
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+    out.push('{');
+    write_fields(out, entry);
+
+    let children = node.children(&tree.tree)
+        .filter(|&c| tree.is_kept(c))
+        .collect::<Vec<NodeId>>();
+
+    if !children.is_empty() {
+        out.push_str(",\"contents\":[\n");
+        for (i, &child) in children.iter().enumerate() {
+            write_entry(out, tree, child, depth + 1);
+            out.push_str(if i == children.len() - 1 { "\n" } else { ",\n" });
+        }
+
+        for _ in 0..depth {
+            out.push_str("  ");
+        }
+        out.push(']');
+    }
+
+    out.push('}');
+}
+
+fn write_fields(out: &mut String, entry: &FsEntry) {
+    let ty = match entry.ft {
+        FileType::File => "file",
+        FileType::Dir | FileType::RestrictedDir | FileType::MountPoint => "directory",
+        FileType::LinkTo { .. } => "link",
+        FileType::Stdin => "stdin",
+    };
+    write!(out, "\"type\":\"{}\",", ty).unwrap();
+    write_name(out, "name", &entry.name);
+
+    match &entry.ft {
+        FileType::LinkTo { target, kind } => {
+            out.push(',');
+            write_name(out, "target", target.as_os_str());
+            match kind {
+                LinkKind::Broken => out.push_str(",\"error\":\"broken link\""),
+                LinkKind::Loop => out.push_str(",\"error\":\"recursive, not followed\""),
+                LinkKind::File | LinkKind::Dir => {}
+            }
+        }
+        FileType::RestrictedDir => {
+            out.push_str(",\"error\":\"error opening dir\"");
+        }
+        FileType::MountPoint => {
+            out.push_str(",\"mount_point\":true");
+        }
+        FileType::Dir => {
+            write!(out, ",\"total_size\":{}", entry.total_size).unwrap();
+        }
+        _ => {}
+    }
+
+    if let Some(meta) = entry.meta {
+        out.push_str(",\"metadata\":");
+        write_metadata(out, &meta);
+    }
+}
+
+fn write_metadata(out: &mut String, meta: &FsMetadata) {
+    write!(
+        out,
+        "{{\"size\":{},\"mtime\":{},\"ctime\":{},\"atime\":{},\"mode\":{},\
+         \"uid\":{},\"gid\":{},\"inode\":{},\"nlink\":{}}}",
+        meta.size,
+        meta.mtime,
+        meta.ctime,
+        meta.atime,
+        meta.mode,
+        meta.uid,
+        meta.gid,
+        meta.inode,
+        meta.nlink
+    ).unwrap();
+}
+
+/// Write `name` as the field `key`.
+///
+/// JSON strings can't hold invalid UTF-8, so a name that isn't valid UTF-8 is
+/// written with it replaced, followed by its exact bytes in `<key>_bytes`.
+fn write_name(out: &mut String, key: &str, name: &OsStr) {
+    write!(out, "\"{}\":", key).unwrap();
+    write_string(out, &name.to_string_lossy());
+
+    if name.to_str().is_none() {
+        write!(out, ",\"{}_bytes\":[", key).unwrap();
+        for (i, b) in name.as_bytes().iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write!(out, "{}", b).unwrap();
+        }
+        out.push(']');
+    }
+}
+
+/// Write `s` as a quoted JSON string, escaping as necessary.
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    fn test_dir(dir: &str) -> PathBuf {
+        PathBuf::new().join("resources/test").join(dir)
+    }
+
+    #[test]
+    fn test_json() {
+        let t = Tree::new_from_dir(&test_dir("one_dir")).unwrap();
+        let json = tree_to_json(&t);
+        let lines = json.lines().collect::<Vec<&str>>();
+
+        assert_eq!("[", lines[0]);
+        assert!(lines[1].starts_with(
+            "  {\"type\":\"directory\",\"name\":\"resources/test/one_dir\",\"total_size\":"
+        ));
+        assert!(lines[1].ends_with(",\"contents\":["));
+        assert!(lines[2].starts_with("    {\"type\":\"directory\",\"name\":\"mydir\","));
+        assert!(lines[3].starts_with("      {\"type\":\"file\",\"name\":\"myfile\",\"metadata\":"));
+        assert!(lines[3].ends_with("}"));
+        assert_eq!("    ]},", lines[4]);
+        assert!(lines[5].starts_with("    {\"type\":\"file\",\"name\":\"myotherfile\","));
+        assert_eq!("  ]},", lines[6]);
+        assert_eq!(
+            "  {\"type\":\"report\",\"directories\":1,\"files\":2}",
+            lines[7]
+        );
+        assert_eq!("]", lines[8]);
+    }
+
+    #[test]
+    fn test_json_link() {
+        let t = Tree::new_from_dir(&test_dir("link")).unwrap();
+        let json = tree_to_json(&t);
+
+        assert!(json.contains("{\"type\":\"link\",\"name\":\"dest\",\"target\":\"source\","));
+    }
+
+    #[test]
+    fn test_json_non_utf8() {
+        use std::ffi::OsStr;
+        use std::fs::File;
+        use std::os::unix::ffi::OsStrExt;
+        use util::TempDir;
+
+        let dir = TempDir::new("json-non-utf8");
+        File::create(dir.join(OsStr::from_bytes(b"a\xffb"))).unwrap();
+        File::create(dir.join("plain")).unwrap();
+
+        let t = Tree::new_from_dir(&dir).unwrap();
+        let json = tree_to_json(&t);
+
+        assert!(json.contains("\"name\":\"a\u{FFFD}b\",\"name_bytes\":[97,255,98],"));
+        assert!(json.contains("\"name\":\"plain\",\"metadata\":"));
+    }
+
+    #[test]
+    fn test_write_string() {
+        let mut out = String::new();
+        write_string(&mut out, "a \"quoted\"\\path\n\u{1}é");
+        assert_eq!("\"a \\\"quoted\\\"\\\\path\\n\\u0001é\"", out);
+    }
+}
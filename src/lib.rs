@@ -1,12 +1,24 @@
+extern crate globset;
 extern crate ignore;
 extern crate indextree;
+extern crate libc;
+#[cfg(test)]
+extern crate quickcheck;
+extern crate regex;
 extern crate termion;
+extern crate unicode_width;
 
-mod fs;
+pub mod config;
+pub mod error;
+pub mod fs;
+pub mod input;
+pub mod json;
 pub mod options;
 pub mod render;
+pub mod search;
 pub mod term;
 pub mod tree;
 pub mod util;
+pub mod watch;
 
 pub use termion::color;
@@ -4,9 +4,14 @@ extern crate itree;
 mod args;
 
 use args::*;
-use itree::{options, render, term, tree};
+use itree::config::Config;
+use itree::{error, json, options, render, term, tree};
 
+use std::fs::File;
 use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::process;
 use std::sync::mpsc::channel;
 use std::thread;
 use std::time::Duration;
@@ -16,23 +21,82 @@ const VERSION: &str = "0.3.1";
 fn main() {
     let (fs_opts, rd_opts, rm) = parse_args(VERSION);
 
-    let mut t = build_tree_loading(fs_opts);
+    // Only the interactive navigator is configurable, so don't let a bad config
+    // file get in the way of anything else.
+    let config = match rm {
+        args::RenderMethod::FullInteractive { .. } => Config::load().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        }),
+        _ => Config::default(),
+    };
+
+    // The navigator shows the tree while it is still being built.
+    let built = match rm {
+        args::RenderMethod::FullInteractive { .. } => tree::Tree::new_scanning(fs_opts),
+        _ => build_tree_loading(fs_opts),
+    };
+    let mut t = built.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    if let args::RenderMethod::FullInteractive { watch: true, .. } = rm {
+        if let Err(e) = t.watch() {
+            eprintln!("Could not watch for changes: {}", e);
+            process::exit(1);
+        }
+    }
     let mut render = render::TreeRender::new(&mut t, rd_opts);
 
     match rm {
         args::RenderMethod::JustSummary => {
-            println!("\n{}", render.tree.summary());
+            println!("\n{}", render.summary());
         }
         args::RenderMethod::NoInteractive => {
             print!("{}", render);
         }
-        args::RenderMethod::FullInteractive => {
-            term::navigate(&mut render);
+        args::RenderMethod::Json => {
+            print!("{}", json::tree_to_json(render.tree));
+        }
+        args::RenderMethod::FullInteractive { choosedir, .. } => {
+            if let Some(path) = term::navigate(&mut render, &config.keys) {
+                match choosedir {
+                    Some(file) => write_choosedir(&file, path),
+                    None => print_path(&path),
+                }
+            }
         }
     }
 }
 
-fn build_tree_loading(opts: options::FsOptions<String>) -> tree::Tree {
+/// Write the directory of a chosen path to `file`, for shell wrappers to `cd` into.
+fn write_choosedir(file: &str, mut path: PathBuf) {
+    if !path.is_dir() {
+        path.pop();
+    }
+
+    let res = File::create(file).and_then(|mut f| f.write_all(path.as_os_str().as_bytes()));
+    if let Err(e) = res {
+        eprintln!("Could not write chosen directory to {}: {}", file, e);
+        process::exit(1);
+    }
+}
+
+/// Print a chosen path to stdout as it is, even if it isn't valid UTF-8.
+fn print_path(path: &PathBuf) {
+    let mut stdout = io::stdout();
+    let res = stdout
+        .write_all(path.as_os_str().as_bytes())
+        .and_then(|_| stdout.write_all(b"\n"));
+    if let Err(e) = res {
+        eprintln!("Could not print chosen path: {}", e);
+        process::exit(1);
+    }
+}
+
+fn build_tree_loading(
+    opts: options::FsOptions<String>,
+) -> Result<tree::Tree, error::ItreeError> {
     let (sx, rx) = channel();
     thread::spawn(move || {
         // Only start loading dialog if it takes more than 300ms to build the tree
@@ -56,5 +120,15 @@ fn build_tree_loading(opts: options::FsOptions<String>) -> tree::Tree {
     let t = tree::Tree::new_with_options(opts);
     sx.send(()).unwrap();
 
+    // Unreadable directories are already marked in the tree itself.
+    if let Ok(t) = &t {
+        for e in t.errors() {
+            match e.error {
+                error::ItreeError::PermissionDenied(_) => {}
+                ref e => eprintln!("{}", e),
+            }
+        }
+    }
+
     t
 }
@@ -4,14 +4,125 @@ use std::path::Path;
 use ignore::overrides::OverrideBuilder;
 use termion::color::{self, Color};
 
+use search::Matcher;
+
+/// How to display the sizes of files and directories.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SizeFormat {
+    /// Exact sizes, in bytes.
+    Bytes,
+    /// Sizes scaled to K, M, G, etc., like `tree -h`.
+    Human,
+}
+
+/// What to order the entries in each directory by.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SortBy {
+    /// Name, byte by byte, so that e.g. `Makefile` comes before `a.txt`.
+    Name,
+    /// Name, ignoring case and comparing runs of digits by their value, so
+    /// that e.g. `a.txt` comes before `Makefile`, and `v2` before `v10`.
+    Natural,
+    /// Size, largest first. Directories are sorted by their own size, not
+    /// that of everything beneath them.
+    Size,
+    /// Modification time, newest first.
+    Mtime,
+    /// Status change time, newest first.
+    Ctime,
+    /// Extension, then name. Entries without an extension come first.
+    Ext,
+    /// Unsorted: entries stay in the order they were added to the tree. A
+    /// directory read all at once is in name order, but entries found by a
+    /// scan, or while watching, go after those already there.
+    None,
+}
+
+const SORT_BYS: &[SortBy] = &[
+    SortBy::Name,
+    SortBy::Natural,
+    SortBy::Size,
+    SortBy::Mtime,
+    SortBy::Ctime,
+    SortBy::Ext,
+    SortBy::None,
+];
+
+impl SortBy {
+    /// The name by which the order is given to `--sort`.
+    pub fn name(self) -> &'static str {
+        match self {
+            SortBy::Name => "name",
+            SortBy::Natural => "natural",
+            SortBy::Size => "size",
+            SortBy::Mtime => "mtime",
+            SortBy::Ctime => "ctime",
+            SortBy::Ext => "ext",
+            SortBy::None => "none",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        SORT_BYS.iter().cloned().find(|s| s.name() == name)
+    }
+
+    /// The order after this one, cycling back to `Name` after the last.
+    ///
+    /// `None` is skipped, since the order entries were read in is lost once
+    /// they have been sorted.
+    pub fn next(self) -> Self {
+        match self {
+            SortBy::Name => SortBy::Natural,
+            SortBy::Natural => SortBy::Size,
+            SortBy::Size => SortBy::Mtime,
+            SortBy::Mtime => SortBy::Ctime,
+            SortBy::Ctime => SortBy::Ext,
+            SortBy::Ext | SortBy::None => SortBy::Name,
+        }
+    }
+}
+
+/// How to order the entries in each directory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SortOrder {
+    pub by: SortBy,
+    /// Whether to reverse the order. Directories still come first if
+    /// `dirs_first` is set.
+    pub reverse: bool,
+    /// Whether to put directories before everything else.
+    pub dirs_first: bool,
+}
+
+impl Default for SortOrder {
+    /// By name, not reversed, without directories first.
+    fn default() -> Self {
+        Self {
+            by: SortBy::Name,
+            reverse: false,
+            dirs_first: false,
+        }
+    }
+}
+
 pub struct RenderOptions {
     pub fg_color: Box<Color>,
     pub bg_color: Box<Color>,
+    pub match_color: Box<dyn Color>,
+    pub change_color: Box<dyn Color>,
+    pub size: Option<SizeFormat>,
+    pub filter: Option<Matcher>,
+    pub resolve_links: bool,
+    pub truncate: bool,
 }
 
 impl fmt::Debug for RenderOptions {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{{ fg_color: ?, bg_color: ? }}")
+        write!(
+            f,
+            "{{ fg_color: ?, bg_color: ?, match_color: ?, change_color: ?, size: {:?}, \
+             filter: {:?}, resolve_links: {:?}, truncate: {:?} }}",
+            self.size, self.filter, self.resolve_links, self.truncate
+        )
     }
 }
 
@@ -20,6 +131,12 @@ impl RenderOptions {
         Self {
             fg_color: Box::new(color::White),
             bg_color: Box::new(color::Blue),
+            match_color: Box::new(color::Yellow),
+            change_color: Box::new(color::Green),
+            size: None,
+            filter: None,
+            resolve_links: false,
+            truncate: false,
         }
     }
 
@@ -32,6 +149,53 @@ impl RenderOptions {
         self.bg_color = color;
         self
     }
+
+    /// Set the color in which to highlight search matches.
+    pub fn match_color(&mut self, color: Box<dyn Color>) -> &mut Self {
+        self.match_color = color;
+        self
+    }
+
+    /// Set the color in which to highlight entries changed while watching the FS.
+    pub fn change_color(&mut self, color: Box<dyn Color>) -> &mut Self {
+        self.change_color = color;
+        self
+    }
+
+    /// Set the format in which to show sizes. `None` hides them.
+    ///
+    /// `None` by default.
+    pub fn size(&mut self, size: Option<SizeFormat>) -> &mut Self {
+        self.size = size;
+        self
+    }
+
+    /// Set a pattern to prune the tree to, keeping only matches and their ancestors.
+    /// `None` shows the whole tree.
+    ///
+    /// `None` by default.
+    pub fn filter(&mut self, filter: Option<Matcher>) -> &mut Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Set whether to show links' targets as the absolute paths they resolve
+    /// to, rather than as read from the link.
+    ///
+    /// `false` by default.
+    pub fn resolve_links(&mut self, resolve_links: bool) -> &mut Self {
+        self.resolve_links = resolve_links;
+        self
+    }
+
+    /// Set whether to cut lines too long for the terminal short, with an
+    /// ellipsis, rather than wrapping them. Only affects interactive rendering.
+    ///
+    /// `false` by default.
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
 }
 
 pub fn validate_ignore(pat: &str) -> Result<(), String> {
@@ -45,6 +209,7 @@ pub fn validate_ignore(pat: &str) -> Result<(), String> {
 pub struct FsOptions<P: AsRef<Path>> {
     pub root: P,
     pub max_depth: Option<usize>,
+    pub lazy_depth: Option<usize>,
     pub follow_links: bool,
     pub max_filesize: Option<u64>,
     pub hidden: bool,
@@ -52,6 +217,9 @@ pub struct FsOptions<P: AsRef<Path>> {
     pub no_ignore: bool,
     pub no_git_exclude: bool,
     pub custom_ignore: Vec<String>,
+    pub sort: SortOrder,
+    pub same_file_system: bool,
+    pub escape_names: bool,
 }
 
 impl<P: AsRef<Path>> FsOptions<P> {
@@ -59,6 +227,7 @@ impl<P: AsRef<Path>> FsOptions<P> {
         Self {
             root,
             max_depth: None,
+            lazy_depth: None,
             follow_links: false,
             max_filesize: None,
             hidden: true,
@@ -66,6 +235,9 @@ impl<P: AsRef<Path>> FsOptions<P> {
             no_ignore: true,
             no_git_exclude: true,
             custom_ignore: Vec::new(),
+            sort: SortOrder::default(),
+            same_file_system: false,
+            escape_names: false,
         }
     }
 
@@ -83,6 +255,15 @@ impl<P: AsRef<Path>> FsOptions<P> {
         self
     }
 
+    /// Set how many levels of the tree to read up front, leaving directories
+    /// deeper than that to be read on demand. `None` reads everything up front.
+    ///
+    /// `None` by default.
+    pub fn lazy_depth(&mut self, lazy_depth: Option<usize>) -> &mut Self {
+        self.lazy_depth = lazy_depth;
+        self
+    }
+
     /// Set whether or not to follow links.
     ///
     /// Disabled by default.
@@ -136,4 +317,66 @@ impl<P: AsRef<Path>> FsOptions<P> {
         self.custom_ignore.push(path.to_owned());
         self
     }
+
+    /// Set what to order the entries in each directory by.
+    ///
+    /// `SortBy::Name` by default.
+    pub fn sort_by(&mut self, by: SortBy) -> &mut Self {
+        self.sort.by = by;
+        self
+    }
+
+    /// Set whether or not to reverse the order of the entries in each directory.
+    ///
+    /// Disabled by default.
+    pub fn reverse(&mut self, reverse: bool) -> &mut Self {
+        self.sort.reverse = reverse;
+        self
+    }
+
+    /// Set whether or not to put directories before everything else.
+    ///
+    /// Disabled by default.
+    pub fn dirs_first(&mut self, dirs_first: bool) -> &mut Self {
+        self.sort.dirs_first = dirs_first;
+        self
+    }
+
+    /// Set whether or not to stay on the file system of the root, leaving
+    /// directories that other file systems are mounted on unread.
+    ///
+    /// Disabled by default.
+    pub fn same_file_system(&mut self, same_file_system: bool) -> &mut Self {
+        self.same_file_system = same_file_system;
+        self
+    }
+
+    /// Set whether or not to show names, and match them, with control
+    /// characters and invalid UTF-8 escaped, rather than with invalid UTF-8
+    /// replaced by U+FFFD.
+    ///
+    /// Disabled by default.
+    pub fn escape_names(&mut self, escape_names: bool) -> &mut Self {
+        self.escape_names = escape_names;
+        self
+    }
+
+    /// A copy of these options with a different type of root.
+    pub fn with_root<Q: AsRef<Path>>(&self, root: Q) -> FsOptions<Q> {
+        FsOptions {
+            root,
+            max_depth: self.max_depth,
+            lazy_depth: self.lazy_depth,
+            follow_links: self.follow_links,
+            max_filesize: self.max_filesize,
+            hidden: self.hidden,
+            only_dirs: self.only_dirs,
+            no_ignore: self.no_ignore,
+            no_git_exclude: self.no_git_exclude,
+            custom_ignore: self.custom_ignore.clone(),
+            sort: self.sort,
+            same_file_system: self.same_file_system,
+            escape_names: self.escape_names,
+        }
+    }
 }
@@ -1,12 +1,19 @@
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::HashMap;
 use std::fmt;
 use std::io::{self, Write};
+use std::mem;
 use std::ops::Deref;
+use std::path::PathBuf;
 
 use indextree::NodeId;
 use termion::color::{Bg, Fg, Reset};
+use unicode_width::UnicodeWidthChar;
 
-use fs::FileType;
-use options::RenderOptions;
+use fs::{resolve_link, FileType, LinkKind};
+use options::{RenderOptions, SizeFormat, SortBy};
+use search::Matcher;
 use tree::{PrefixPiece, Tree};
 
 pub const MID_BRANCH: &str = "├──";
@@ -16,47 +23,206 @@ pub const BAR_INDENT: &str = "│   ";
 
 pub const FOLD_MARK: &str = "*";
 pub const RESTRICTED_MARK: &str = " [error opening dir]";
+pub const MOUNT_MARK: &str = " [mount point]";
 pub const LINK_MARK: &str = " -> ";
+pub const BROKEN_MARK: &str = " [broken link]";
+pub const LOOP_MARK: &str = " [recursive, not followed]";
+pub const ELLIPSIS: &str = "…";
+
+/// How many columns the tree pans by at a time: one level of indentation.
+const SCROLL_STEP: usize = 4;
+
+const SIZE_UNITS: &[char] = &['K', 'M', 'G', 'T', 'P', 'E'];
+
+/// Format a number of bytes like `tree -h` does, e.g. `512`, `4.0K`, `12M`.
+pub fn human_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{}", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if size < 9.95 {
+        format!("{:.1}{}", size, SIZE_UNITS[unit])
+    } else {
+        format!("{:.0}{}", size, SIZE_UNITS[unit])
+    }
+}
+
+/// The byte offsets in `text` at which each row starts, when a terminal
+/// `width` columns wide wraps it.
+///
+/// A double-width character that doesn't fit at the end of a row starts the next.
+fn row_starts(text: &str, width: usize) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut col = 0;
+    for (i, c) in text.char_indices() {
+        let w = c.width().unwrap_or(0);
+        if col > 0 && col + w > width {
+            starts.push(i);
+            col = 0;
+        }
+        col += w;
+    }
+    starts
+}
+
+/// The part of `text` that fits in `rows` rows of a terminal `width` columns wide.
+fn clip_to_rows(text: &str, rows: usize, width: usize) -> &str {
+    match row_starts(text, width).get(rows) {
+        Some(&end) => &text[..end],
+        None => text,
+    }
+}
+
+/// The number of columns `text` takes up on a terminal.
+fn text_width(text: &str) -> usize {
+    text.chars().map(|c| c.width().unwrap_or(0)).sum()
+}
+
+/// The longest start of `text` that fits in `cols` columns.
+fn take_cols(text: &str, cols: usize) -> &str {
+    let mut width = 0;
+    for (i, c) in text.char_indices() {
+        width += c.width().unwrap_or(0);
+        if width > cols {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+/// What's left of `text` after dropping up to `cols` columns from its start,
+/// taking the columns dropped off `cols`.
+///
+/// A double-width character split by the cut is dropped whole.
+fn skip_cols<'t>(text: &'t str, cols: &mut usize) -> &'t str {
+    for (i, c) in text.char_indices() {
+        if *cols == 0 {
+            return &text[i..];
+        }
+        *cols = cols.saturating_sub(c.width().unwrap_or(0));
+    }
+    ""
+}
+
+/// Shorten `name` to fit in `cols` columns, ending it with an ellipsis, but
+/// keeping its extension if there's room.
+fn truncate_name(name: &str, cols: usize) -> String {
+    let ext = match name.rfind('.') {
+        Some(i) if i > 0 => &name[i..],
+        _ => "",
+    };
+    let ext_width = text_width(ext);
+
+    if ext_width > 0 && ext_width + 2 <= cols {
+        let stem = &name[..name.len() - ext.len()];
+        format!("{}{}{}", take_cols(stem, cols - 1 - ext_width), ELLIPSIS, ext)
+    } else {
+        format!("{}{}", take_cols(name, cols.saturating_sub(1)), ELLIPSIS)
+    }
+}
+
+/// An active search or filter: its pattern, and the nodes it matched in tree order.
+struct Search {
+    matcher: Matcher,
+    hits: Vec<NodeId>,
+}
 
 pub struct TreeRender<'a> {
     pub tree: &'a mut Tree,
     opts: RenderOptions,
+    search: Option<Search>,
+    filter: Option<Search>,
+    /// How many columns the tree is panned to the right by, when truncating
+    /// lines. Kept within the focused line's lead, which rendering may shrink.
+    scroll: Cell<usize>,
+    /// The targets of the links drawn so far, resolved, by node. Resolving a
+    /// link reads the FS, so it is only done once.
+    resolved: RefCell<HashMap<NodeId, PathBuf>>,
 }
 
 impl<'a> fmt::Display for TreeRender<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "{}", self.tree.tree[self.tree.root].data.name)?;
+        writeln!(f, "{}", self.tree.display_name(self.tree.root))?;
 
-        let mut l_ind = 1;
+        let mut l_ind = self.tree.lines.lines[0].next;
         while let Some(line) = &self.tree.lines.lines.get(l_ind) {
             writeln!(
                 f,
-                "{} {}{}",
+                "{} {}{}{}",
                 self.prefix_string(&line.prefix),
-                self.tree.tree[line.node].data.name,
+                self.size_string(line.node),
+                self.tree.display_name(line.node),
                 self.suffix_for_node(line.node)
             )?;
 
             l_ind = line.next;
         }
 
-        writeln!(f, "\n{}", self.tree.summary())?;
+        writeln!(f, "\n{}", self.summary())?;
 
         Ok(())
     }
 }
 
 impl<'a> TreeRender<'a> {
-    pub fn new(tree: &'a mut Tree, opts: RenderOptions) -> Self {
-        Self { tree, opts }
+    pub fn new(tree: &'a mut Tree, mut opts: RenderOptions) -> Self {
+        let filter = opts.filter.take();
+
+        let mut render = Self {
+            tree,
+            opts,
+            search: None,
+            filter: None,
+            scroll: Cell::new(0),
+            resolved: RefCell::new(HashMap::new()),
+        };
+        render.filter(filter);
+
+        render
     }
 
     pub fn focus_up(&mut self) {
         self.tree.focus_up();
     }
 
+    /// Pan the tree left, towards the start of its lines.
+    ///
+    /// Does nothing unless lines are truncated.
+    pub fn scroll_left(&mut self) {
+        if self.opts.truncate {
+            self.scroll.set(self.scroll.get().saturating_sub(SCROLL_STEP));
+        }
+    }
+
+    /// Pan the tree right, at most far enough to bring the focused name to
+    /// the left edge of the screen, to see into trees too deep to fit.
+    ///
+    /// Does nothing unless lines are truncated.
+    pub fn scroll_right(&mut self) {
+        if self.opts.truncate {
+            self.scroll.set(self.scroll.get() + SCROLL_STEP);
+            self.clamp_scroll();
+        }
+    }
+
+    /// Pan back as far as needed to keep the focused name on the screen, as
+    /// the focus may have moved to a shallower line since the tree was panned.
+    fn clamp_scroll(&self) {
+        let y = self.tree.lines.inds[&self.tree.focused];
+        let max = text_width(&self.lead_string(y));
+        self.scroll.set(cmp::min(self.scroll.get(), max));
+    }
+
     pub fn focus_down(&mut self) {
         self.tree.focus_down();
+        self.update_matches();
     }
 
     pub fn focus_left(&mut self) {
@@ -69,6 +235,243 @@ impl<'a> TreeRender<'a> {
 
     pub fn toggle_focus_fold(&mut self) {
         self.tree.toggle_focus_fold();
+        self.update_matches();
+    }
+
+    /// Add the entries read by the scan since the last poll to the tree, and
+    /// bring the search and filter up to date.
+    ///
+    /// Returns whether the tree changed, or the scan finished.
+    pub fn poll_scan(&mut self) -> bool {
+        let changed = self.tree.poll_scan();
+        self.update_matches();
+        changed
+    }
+
+    /// Apply the changes to the FS seen since the last poll to the tree, and
+    /// bring the search and filter up to date.
+    ///
+    /// Returns whether the tree changed.
+    pub fn poll_watch(&mut self) -> bool {
+        let changed = self.tree.poll_watch();
+        self.update_matches();
+        changed
+    }
+
+    /// Read the focused directory again from the FS, and bring the search and
+    /// filter up to date.
+    pub fn refresh_focused(&mut self) {
+        self.tree.refresh_focused();
+        self.update_matches();
+    }
+
+    /// Read the whole tree again from the FS, and bring the search and filter
+    /// up to date.
+    pub fn rebuild(&mut self) {
+        self.tree.rebuild();
+        self.update_matches();
+    }
+
+    /// Re-order the tree by the next kind of sort order, keeping whether it
+    /// is reversed and has directories first, and bring the search and filter
+    /// up to date.
+    ///
+    /// Returns what the tree is now sorted by.
+    pub fn cycle_sort(&mut self) -> SortBy {
+        let mut sort = self.tree.sort_order();
+        sort.by = sort.by.next();
+        self.tree.sort(sort);
+        self.update_matches();
+        sort.by
+    }
+
+    /// Bring the search and filter up to date, if entries have been read
+    /// into the tree since they ran.
+    fn update_matches(&mut self) {
+        let scanned = mem::take(&mut self.tree.scanned);
+        if !mem::replace(&mut self.tree.loaded_more, false) {
+            // Only entries have been added, so only they need matching.
+            for s in self.filter.iter_mut().chain(self.search.iter_mut()) {
+                self.tree.add_matches(&s.matcher, &scanned, &mut s.hits);
+            }
+            return;
+        }
+
+        match self.filter.take() {
+            Some(f) => self.filter(Some(f.matcher)),
+            None => {
+                if let Some(s) = self.search.as_mut() {
+                    s.hits = self.tree.find_matches(&s.matcher);
+                }
+            }
+        }
+    }
+
+    /// Move the focus to `node`, removing the filter if it hides the node.
+    pub fn focus_node(&mut self, node: NodeId) {
+        if !self.tree.is_kept(node) {
+            self.filter(None);
+        }
+        self.tree.focus_node(node);
+        self.update_matches();
+    }
+
+    /// Search the whole tree for names matching `matcher`, and focus the first
+    /// match at or after the focused line.
+    pub fn search(&mut self, matcher: Matcher) {
+        let hits = self.tree.find_matches(&matcher);
+        self.search = Some(Search { matcher, hits });
+
+        let focused = self.tree.focused;
+        if !self.is_hit(focused) {
+            self.search_next();
+        }
+    }
+
+    pub fn clear_search(&mut self) {
+        self.search = None;
+    }
+
+    /// The number of nodes matched by the active search, if any.
+    pub fn search_hits(&self) -> Option<usize> {
+        self.search.as_ref().map(|s| s.hits.len())
+    }
+
+    /// Focus the next search match after the focused line, wrapping around.
+    pub fn search_next(&mut self) {
+        let cur = self.tree.lines.inds[&self.tree.focused];
+        let next = match &self.search {
+            Some(s) => s.hits
+                .iter()
+                .find(|n| self.tree.lines.inds[n] > cur)
+                .or(s.hits.first())
+                .cloned(),
+            None => None,
+        };
+
+        if let Some(n) = next {
+            self.tree.focus_node(n);
+        }
+    }
+
+    /// Focus the previous search match before the focused line, wrapping around.
+    pub fn search_prev(&mut self) {
+        let cur = self.tree.lines.inds[&self.tree.focused];
+        let prev = match &self.search {
+            Some(s) => s.hits
+                .iter()
+                .rev()
+                .find(|n| self.tree.lines.inds[n] < cur)
+                .or(s.hits.last())
+                .cloned(),
+            None => None,
+        };
+
+        if let Some(n) = prev {
+            self.tree.focus_node(n);
+        }
+    }
+
+    /// Prune the tree to the nodes matching `matcher` and their ancestors.
+    /// `None` removes the filter.
+    pub fn filter(&mut self, matcher: Option<Matcher>) {
+        if matcher.is_none() && self.filter.is_none() {
+            return;
+        }
+
+        let hits = self.tree.filter(matcher.as_ref());
+        self.filter = matcher.map(|matcher| Search { matcher, hits });
+
+        // Hits may have been filtered out.
+        if let Some(s) = self.search.as_mut() {
+            s.hits = self.tree.find_matches(&s.matcher);
+        }
+    }
+
+    pub fn filter_matcher(&self) -> Option<&Matcher> {
+        self.filter.as_ref().map(|s| &s.matcher)
+    }
+
+    /// The number of nodes matched by the active filter, if any.
+    pub fn filter_hits(&self) -> Option<usize> {
+        self.filter.as_ref().map(|s| s.hits.len())
+    }
+
+    fn is_hit(&self, node: NodeId) -> bool {
+        match &self.search {
+            Some(s) => s.matcher.is_match(&self.tree.display_name(node)),
+            None => false,
+        }
+    }
+
+    /// The name of a node, as shown by `name`, with any search (or, failing
+    /// that, filter) match highlighted.
+    ///
+    /// Entries recently changed by the FS being watched are drawn in `change_color`.
+    fn highlighted_name(&self, node: NodeId, name: String) -> String {
+        let span = match self.search.as_ref().or(self.filter.as_ref()) {
+            Some(s) if node != self.tree.root => s.matcher.find(&name),
+            _ => None,
+        };
+        let changed = self.tree.changed.contains_key(&node);
+        let color = if changed {
+            self.opts.change_color.deref()
+        } else {
+            self.opts.fg_color.deref()
+        };
+
+        let name = match span {
+            Some((start, end)) => format!(
+                "{}{}{}{}{}",
+                &name[..start],
+                Fg(self.opts.match_color.deref()),
+                &name[start..end],
+                Fg(color),
+                &name[end..],
+            ),
+            None => name,
+        };
+
+        if changed {
+            format!("{}{}{}", Fg(color), name, Fg(self.opts.fg_color.deref()))
+        } else {
+            name
+        }
+    }
+
+    /// Summary information about the tree, including its total size if sizes are shown.
+    pub fn summary(&self) -> String {
+        match self.opts.size {
+            Some(fmt) => format!(
+                "{} used in {}",
+                self.format_size(self.tree.total_size(), fmt),
+                self.tree.summary()
+            ),
+            None => self.tree.summary(),
+        }
+    }
+
+    fn format_size(&self, bytes: u64, fmt: SizeFormat) -> String {
+        match fmt {
+            SizeFormat::Bytes => format!("{}", bytes),
+            SizeFormat::Human => human_size(bytes),
+        }
+    }
+
+    /// The size column for a node, or an empty string if sizes are hidden.
+    ///
+    /// The root has no size column; its size is reported in the summary.
+    fn size_string(&self, node: NodeId) -> String {
+        if node == self.tree.root {
+            return String::new();
+        }
+
+        let size = self.tree.tree[node].data.total_size;
+        match self.opts.size {
+            Some(SizeFormat::Bytes) => format!("[{:>11}]  ", size),
+            Some(SizeFormat::Human) => format!("[{:>4}]  ", human_size(size)),
+            None => String::new(),
+        }
     }
 
     fn prefix_string(&self, prefix: &Vec<PrefixPiece>) -> String {
@@ -86,20 +489,31 @@ impl<'a> TreeRender<'a> {
         match &self.tree.tree[node].data.ft {
             FileType::File => String::new(),
             FileType::Dir => {
-                if self.tree
-                    .lines
-                    .folded
-                    .contains(&self.tree.lines.inds[&node])
-                {
+                if self.tree.lines.folded.contains(&node) {
                     FOLD_MARK.to_owned()
                 } else {
                     String::new()
                 }
             }
             FileType::RestrictedDir => RESTRICTED_MARK.to_owned(),
-            FileType::LinkTo(dest) => {
+            FileType::MountPoint => MOUNT_MARK.to_owned(),
+            FileType::LinkTo { target, kind } => {
                 let mut s = String::from(LINK_MARK);
-                s.push_str(dest);
+                let target = if self.opts.resolve_links {
+                    self.resolved
+                        .borrow_mut()
+                        .entry(node)
+                        .or_insert_with(|| resolve_link(self.tree.tree[node].data.de.path(), target))
+                        .clone()
+                } else {
+                    target.clone()
+                };
+                s.push_str(&self.tree.display_os_str(target.as_os_str()));
+                match kind {
+                    LinkKind::Broken => s.push_str(BROKEN_MARK),
+                    LinkKind::Loop => s.push_str(LOOP_MARK),
+                    LinkKind::File | LinkKind::Dir => {}
+                }
                 s
             }
             FileType::Stdin => String::from("<stdin>"),
@@ -117,31 +531,133 @@ impl<'a> TreeRender<'a> {
         n: usize,
         width: usize,
     ) -> io::Result<()> {
+        self.clamp_scroll();
         let y = self.tree.lines.inds[&self.tree.focused];
-        let (mut start, end) = self.bounds_of_range_around_line(y, n, width);
+        let (start, end) = self.bounds_of_range_around_line(y, n, width);
 
-        print!("{}", Fg(self.opts.fg_color.deref()));
-        while start < end {
-            let next = self.tree.lines.lines[start].next;
-            let last = self.tree.lines.lines.get(next).is_none() || next >= end;
+        let mut range = Vec::new();
+        let mut i = start;
+        while i < end {
+            range.push(i);
+            i = self.tree.lines.lines[i].next;
+        }
 
-            self.render_line(writer, start, start == y, last)?;
-            start = next
+        // Wrapped lines can take the range over n rows, which would scroll
+        // the terminal, so drop lines from the edges until it fits.
+        let mut rows = range
+            .iter()
+            .map(|&l| self.visual_lines_for_line(l, width))
+            .sum::<usize>();
+        while rows > n && range.len() > 1 {
+            let l = if range[range.len() - 1] != y {
+                range.pop().unwrap()
+            } else {
+                range.remove(0)
+            };
+            rows -= self.visual_lines_for_line(l, width);
+        }
+
+        print!("{}", Fg(self.opts.fg_color.deref()));
+        if rows > n {
+            // Even the focused line alone is too tall, so show what fits of it.
+            self.render_clipped_line(writer, y, n, width)?;
+        } else {
+            for (i, &l) in range.iter().enumerate() {
+                self.render_line(writer, l, l == y, i == range.len() - 1, width)?;
+            }
         }
         print!("{}", Fg(Reset));
 
-        Ok(())
+        writer.flush()
     }
 
-    fn visual_lines_for_line(&self, l_ind: usize, width: usize) -> usize {
-        let line = &self.tree.lines.lines[l_ind];
-        let mut pl = line.prefix.len();
-        if pl != 0 {
-            pl += 1; // If not the root
+    /// Render what fits of a focused line in `rows` rows of a terminal `width`
+    /// columns wide, without highlighting matches.
+    fn render_clipped_line<W: Write>(
+        &self,
+        writer: &mut W,
+        ind: usize,
+        rows: usize,
+        width: usize,
+    ) -> io::Result<()> {
+        write!(
+            writer,
+            "{}{}{}",
+            Bg(self.opts.bg_color.deref()),
+            clip_to_rows(&self.line_text(ind), rows, width),
+            Bg(Reset),
+        )
+    }
+
+    /// The text of a line, as rendered but without colors.
+    fn line_text(&self, ind: usize) -> String {
+        let node = self.tree.lines.lines[ind].node;
+        format!(
+            "{}{}{}",
+            self.lead_string(ind),
+            self.tree.display_name(node),
+            self.suffix_for_node(node),
+        )
+    }
+
+    /// The part of a line before the name: its prefix, and size if shown.
+    fn lead_string(&self, ind: usize) -> String {
+        let line = &self.tree.lines.lines[ind];
+        format!(
+            "{}{}{}",
+            self.prefix_string(&line.prefix),
+            if line.prefix.is_empty() { "" } else { " " },
+            self.size_string(line.node),
+        )
+    }
+
+    /// The lead, name and suffix of a line, panned by `scroll` columns and cut
+    /// to fit on one row of a terminal `width` columns wide.
+    ///
+    /// Cut lines end with an ellipsis, and panned lines start with one. The
+    /// lead is kept whole if possible, then the name's extension.
+    fn truncated_parts(&self, ind: usize, width: usize) -> (String, String, String) {
+        let node = self.tree.lines.lines[ind].node;
+        let (lead, name, suffix) = (
+            self.lead_string(ind),
+            self.tree.display_name(node),
+            self.suffix_for_node(node),
+        );
+
+        // The ellipsis marking a panned line takes the place of its first column.
+        let (lead, name, suffix) = if self.scroll.get() > 0 {
+            let mut skip = self.scroll.get() + 1;
+            let lead = format!("{}{}", ELLIPSIS, skip_cols(&lead, &mut skip));
+            let name = skip_cols(&name, &mut skip).to_owned();
+            let suffix = skip_cols(&suffix, &mut skip).to_owned();
+            (lead, name, suffix)
+        } else {
+            (lead, name, suffix)
+        };
+
+        let (lead_width, name_width) = (text_width(&lead), text_width(&name));
+        if lead_width + name_width + text_width(&suffix) <= width {
+            (lead, name, suffix)
+        } else if lead_width + 1 >= width {
+            let lead = format!("{}{}", take_cols(&lead, width.saturating_sub(1)), ELLIPSIS);
+            (lead, String::new(), String::new())
+        } else if lead_width + name_width < width {
+            let cols = width - lead_width - name_width - 1;
+            let suffix = format!("{}{}", take_cols(&suffix, cols), ELLIPSIS);
+            (lead, name, suffix)
+        } else {
+            let name = truncate_name(&name, width - lead_width);
+            (lead, name, String::new())
         }
-        pl += self.tree.tree[line.node].data.name.len();
+    }
 
-        pl / width + 1
+    /// The number of rows a line takes up on a terminal `width` columns wide.
+    fn visual_lines_for_line(&self, l_ind: usize, width: usize) -> usize {
+        if self.opts.truncate {
+            1
+        } else {
+            row_starts(&self.line_text(l_ind), width).len()
+        }
     }
 
     /// Find the bounds of the range of n consecutively renderable lines
@@ -202,7 +718,49 @@ impl<'a> TreeRender<'a> {
         (start, end)
     }
 
-    /// Render a single line of the tree.
+    /// Render the errors reading the tree in at most `n` rows, as a header
+    /// followed by as many errors as fit, scrolled to show the `selected`
+    /// error highlighted.
+    pub fn render_errors<W: Write>(
+        &self,
+        writer: &mut W,
+        selected: usize,
+        n: usize,
+        width: usize,
+    ) -> io::Result<()> {
+        if n == 0 {
+            return Ok(());
+        }
+
+        let clip = |s: String| clip_to_rows(&s, 1, width).to_owned();
+        write!(writer, "{}", clip(format!("{}:", self.tree.errors_summary())))?;
+
+        let rows = n - 1;
+        let start = if selected >= rows {
+            selected + 1 - rows
+        } else {
+            0
+        };
+        for (i, e) in self.tree.errors.iter().enumerate().skip(start).take(rows) {
+            let text = clip(format!("  {}", e.error));
+            if i == selected {
+                write!(
+                    writer,
+                    "\r\n{}{}{}",
+                    Bg(self.opts.bg_color.deref()),
+                    text,
+                    Bg(Reset)
+                )?;
+            } else {
+                write!(writer, "\r\n{}", text)?;
+            }
+        }
+
+        writer.flush()
+    }
+
+    /// Render a single line of the tree, truncated to `width` columns if
+    /// `truncate` is set.
     ///
     /// Uses \r\n as a line ending since when terminal is in raw mode \n
     /// alone does not move the cursor back to the beginning of the line.
@@ -212,31 +770,39 @@ impl<'a> TreeRender<'a> {
         ind: usize,
         focus: bool,
         last: bool,
+        width: usize,
     ) -> io::Result<()> {
-        let line = &self.tree.lines.lines[ind];
+        let node = self.tree.lines.lines[ind].node;
         let ending = if last { "" } else { "\r\n" };
+        let (lead, name, suffix) = if self.opts.truncate {
+            self.truncated_parts(ind, width)
+        } else {
+            (
+                self.lead_string(ind),
+                self.tree.display_name(node),
+                self.suffix_for_node(node),
+            )
+        };
 
         if focus {
             write!(
                 writer,
-                "{}{}{}{}{}{}{}",
-                self.prefix_string(&line.prefix),
-                if line.prefix.is_empty() { "" } else { " " },
+                "{}{}{}{}{}{}",
+                lead,
                 Bg(self.opts.bg_color.deref()),
-                self.tree.tree[line.node].data.name,
-                self.suffix_for_node(line.node),
+                self.highlighted_name(node, name),
+                suffix,
                 Bg(Reset),
                 ending,
             )
         } else {
             write!(
                 writer,
-                "{}{}{}{}{}",
-                self.prefix_string(&line.prefix),
-                if line.prefix.is_empty() { "" } else { " " },
-                self.tree.tree[line.node].data.name,
-                self.suffix_for_node(line.node),
-                if last { "" } else { "\r\n" }
+                "{}{}{}{}",
+                lead,
+                self.highlighted_name(node, name),
+                suffix,
+                ending,
             )
         }?;
 
@@ -263,7 +829,7 @@ mod tests {
     }
 
     fn draw_to_string(dir: &PathBuf) -> String {
-        let mut t = Tree::new_from_dir(dir);
+        let mut t = Tree::new_from_dir(dir).unwrap();
         format!("{}", TreeRender::new(&mut t, RenderOptions::new()))
     }
 
@@ -338,9 +904,244 @@ mod tests {
         assert_eq!(exp, draw_to_string(&dir));
     }
 
+    #[test]
+    fn test_draw_link_targets() {
+        use fs::absolute_path;
+        use std::fs::{create_dir_all, File};
+        use std::os::unix::fs::symlink;
+        use util::TempDir;
+
+        let dir = TempDir::new("link-targets");
+        create_dir_all(dir.join("app/vendor")).unwrap();
+        create_dir_all(dir.join("shared")).unwrap();
+        File::create(dir.join("shared/lib")).unwrap();
+        symlink("../../shared/lib", dir.join("app/vendor/lib")).unwrap();
+        let abs_dir = absolute_path(&dir).unwrap();
+
+        let mut t = Tree::new_from_dir(&dir.join("app")).unwrap();
+        let raw = format!("{}", TreeRender::new(&mut t, RenderOptions::new()));
+        let mut opts = RenderOptions::new();
+        opts.resolve_links(true);
+        let r = TreeRender::new(&mut t, opts);
+        let resolved = format!("{}", r);
+
+        // The link is only resolved the first time it is drawn.
+        assert_eq!(resolved, format!("{}", r));
+        assert_eq!(1, r.resolved.borrow().len());
+
+        assert!(raw.contains("lib -> ../../shared/lib\n"));
+        assert!(resolved.contains(&format!("lib -> {}\n", abs_dir.join("shared/lib").display())));
+    }
+
+    #[test]
+    fn test_draw_non_utf8() {
+        use options::FsOptions;
+        use search::MatchKind;
+        use std::ffi::OsStr;
+        use std::fs::File;
+        use std::os::unix::ffi::OsStrExt;
+        use util::TempDir;
+
+        let dir = TempDir::new("non-utf8");
+        File::create(dir.join(OsStr::from_bytes(b"caf\xe9\n.txt"))).unwrap();
+
+        let mut t = Tree::new_from_dir(&dir).unwrap();
+
+        let m = Matcher::new("caf", MatchKind::Substring).unwrap();
+        assert_eq!(1, t.find_matches(&m).len());
+
+        let lossy = format!("{}", TreeRender::new(&mut t, RenderOptions::new()));
+        assert!(lossy.contains(&format!("{} caf\u{FFFD}\n.txt\n", END_BRANCH)));
+
+        let mut opts = FsOptions::new(&dir);
+        opts.escape_names(true);
+        let mut t = Tree::new_with_options(opts).unwrap();
+        let escaped = format!("{}", TreeRender::new(&mut t, RenderOptions::new()));
+        assert!(escaped.contains(&format!("{} caf\\xe9\\n.txt\n", END_BRANCH)));
+    }
+
+    #[test]
+    fn test_render_short_terminal() {
+        let mut t = Tree::new_from_dir(&test_dir("simple")).unwrap();
+        let render = TreeRender::new(&mut t, RenderOptions::new());
+        let render_to_string = |n| {
+            let mut out = Vec::new();
+            render.render_around_focus(&mut out, n, 4).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let bg = Bg(render.opts.bg_color.deref());
+
+        // The focused line, for myfile, wraps onto three rows of 4.
+        assert_eq!(
+            format!("{} {}myfile{}", MID_BRANCH, bg, Bg(Reset)),
+            render_to_string(5)
+        );
+        assert_eq!(
+            format!("{}{} myfi{}", bg, MID_BRANCH, Bg(Reset)),
+            render_to_string(2)
+        );
+        assert_eq!("", render_to_string(0));
+    }
+
+    #[test]
+    fn test_row_starts() {
+        assert_eq!(vec![0], row_starts("", 4));
+        assert_eq!(vec![0], row_starts("abcd", 4));
+        assert_eq!(vec![0, 4], row_starts("abcde", 4));
+        // Box-drawing characters take a column each, CJK characters two.
+        assert_eq!(vec![0, 10], row_starts("├── 日本", 4));
+        // A wide character that doesn't fit at the end of a row starts the next.
+        assert_eq!(vec![0, 2, 5], row_starts("ab日本", 3));
+        assert_eq!("ab日", clip_to_rows("ab日本語", 2, 3));
+    }
+
+    #[test]
+    fn test_visual_lines_wide_names() {
+        use std::fs::{create_dir_all, File};
+        use util::TempDir;
+
+        let dir = TempDir::new("wide-names");
+        create_dir_all(dir.join("日本語のフォルダ")).unwrap();
+        File::create(dir.join("日本語のフォルダ/🌲")).unwrap();
+
+        let mut t = Tree::new_from_dir(&dir).unwrap();
+        t.focus_right();
+        t.toggle_focus_fold();
+        let r = TreeRender::new(&mut t, RenderOptions::new());
+
+        // "├── " then 16 columns of name, and the fold mark: 21 columns.
+        assert_eq!(1, r.visual_lines_for_line(1, 21));
+        assert_eq!(2, r.visual_lines_for_line(1, 20));
+        assert_eq!(3, r.visual_lines_for_line(1, 10));
+    }
+
+    #[test]
+    fn test_truncate_name() {
+        assert_eq!("a_l….txt", truncate_name("a_long_name.txt", 8));
+        assert_eq!("a_long_…", truncate_name("a_long_name", 8));
+        // No room for the extension, or a name that is only an extension.
+        assert_eq!("a_l…", truncate_name("a_long_name.txt", 4));
+        assert_eq!(".bash…", truncate_name(".bashrc_local", 6));
+        assert_eq!("日本…", truncate_name("日本語", 5));
+    }
+
+    #[test]
+    fn test_render_truncated() {
+        use std::fs::File;
+        use util::TempDir;
+
+        let dir = TempDir::new("truncate");
+        File::create(dir.join("a_long_name.txt")).unwrap();
+        File::create(dir.join("short")).unwrap();
+
+        let mut t = Tree::new_from_dir(&dir).unwrap();
+        let mut opts = RenderOptions::new();
+        opts.truncate(true);
+        let mut r = TreeRender::new(&mut t, opts);
+        let render_to_string = |r: &TreeRender| {
+            let mut out = Vec::new();
+            r.render_around_focus(&mut out, 3, 12).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let bg = format!("{}", Bg(r.opts.bg_color.deref()));
+
+        let out = render_to_string(&r);
+        assert!(out.contains(&format!("\r\n{} {}a_l….txt{}\r\n", MID_BRANCH, bg, Bg(Reset))));
+        assert!(out.ends_with(&format!("\r\n{} short", END_BRANCH)));
+
+        // Panning drops the prefix, marking that with an ellipsis, and goes no
+        // further than the focused name.
+        r.scroll_right();
+        r.scroll_right();
+        let out = render_to_string(&r);
+        assert!(out.contains(&format!("\r\n…{}_long_….txt{}\r\n", bg, Bg(Reset))));
+        assert!(out.ends_with("\r\n…hort"));
+
+        r.scroll_left();
+        assert!(render_to_string(&r).ends_with(&format!("\r\n{} short", END_BRANCH)));
+    }
+
+    #[test]
+    fn test_scroll_clamped() {
+        use std::fs::{create_dir_all, File};
+        use util::TempDir;
+
+        let dir = TempDir::new("scroll-clamped");
+        create_dir_all(dir.join("d/e/f")).unwrap();
+        File::create(dir.join("top")).unwrap();
+
+        let mut t = Tree::new_from_dir(&dir).unwrap();
+        let mut opts = RenderOptions::new();
+        opts.truncate(true);
+        let mut r = TreeRender::new(&mut t, opts);
+        let render = |r: &TreeRender| r.render_around_focus(&mut Vec::new(), 5, 40).unwrap();
+
+        r.focus_down();
+        r.focus_down();
+        for _ in 0..3 {
+            r.scroll_right();
+        }
+        assert_eq!(12, r.scroll.get());
+
+        // Moving up to a shallower line pans back to its name when next drawn,
+        // so one step left is enough to see the start of the lines again.
+        r.focus_up();
+        r.focus_up();
+        render(&r);
+        assert_eq!(4, r.scroll.get());
+        r.scroll_left();
+        assert_eq!(0, r.scroll.get());
+
+        let mut t = Tree::new_from_dir(&dir).unwrap();
+        let mut r = TreeRender::new(&mut t, RenderOptions::new());
+        r.scroll_right();
+        assert_eq!(0, r.scroll.get());
+    }
+
+    #[test]
+    fn test_draw_size() {
+        let dir = test_dir("one_dir");
+        let mut t = Tree::new_from_dir(&dir).unwrap();
+        let mut opts = RenderOptions::new();
+        opts.size(Some(SizeFormat::Bytes));
+
+        let dir_size = ::std::fs::metadata(dir.join("mydir")).unwrap().len();
+        let root_size = ::std::fs::metadata(&dir).unwrap().len();
+
+        let exp = format!(
+            "{}\n{} [{:>11}]  {}\n{}{} [{:>11}]  {}\n{} [{:>11}]  {}\n\n{} used in {}\n",
+            dir.display(),
+            MID_BRANCH,
+            dir_size,
+            "mydir",
+            BAR_INDENT,
+            END_BRANCH,
+            0,
+            "myfile",
+            END_BRANCH,
+            0,
+            "myotherfile",
+            root_size + dir_size,
+            "1 directory, 2 files",
+        );
+
+        assert_eq!(exp, format!("{}", TreeRender::new(&mut t, opts)));
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!("0", human_size(0));
+        assert_eq!("1023", human_size(1023));
+        assert_eq!("1.0K", human_size(1024));
+        assert_eq!("1.5K", human_size(1536));
+        assert_eq!("10K", human_size(10 * 1024));
+        assert_eq!("4.0M", human_size(4 * 1024 * 1024));
+        assert_eq!("1.0G", human_size(1024 * 1024 * 1024));
+    }
+
     #[test]
     fn test_focus() {
-        let mut t = Tree::new_from_dir(&test_dir(""));
+        let mut t = Tree::new_from_dir(&test_dir("")).unwrap();
         assert_eq!("link", t.focused().name);
         t.focus_up();
         assert_eq!("link", t.focused().name);
@@ -359,9 +1160,585 @@ mod tests {
         assert_eq!("one_dir", t.focused().name);
     }
 
+    #[test]
+    fn test_lazy() {
+        use options::FsOptions;
+
+        let mut opts = FsOptions::new(test_dir(""));
+        opts.lazy_depth(Some(1));
+        let mut t = Tree::new_with_options(opts).unwrap();
+
+        let exp = format!(
+            "{}\n{} {}\n{} {}\n{} {}\n\n{}\n",
+            "resources/test",
+            MID_BRANCH,
+            "link*",
+            MID_BRANCH,
+            "one_dir*",
+            END_BRANCH,
+            "simple*",
+            "3 directories, 0 files (partial)",
+        );
+        assert_eq!(exp, format!("{}", TreeRender::new(&mut t, RenderOptions::new())));
+
+        t.focus_right();
+        assert_eq!("4 directories, 1 file (partial)", t.summary());
+        t.focus_down();
+        assert_eq!("mydir", t.focused().name);
+
+        let exp = format!(
+            "{}\n{} {}\n{} {}\n{}{} {}\n{}{} {}\n{} {}\n\n{}\n",
+            "resources/test",
+            MID_BRANCH,
+            "link*",
+            MID_BRANCH,
+            "one_dir",
+            BAR_INDENT,
+            MID_BRANCH,
+            "mydir*",
+            BAR_INDENT,
+            END_BRANCH,
+            "myotherfile",
+            END_BRANCH,
+            "simple*",
+            "4 directories, 1 file (partial)",
+        );
+        assert_eq!(exp, format!("{}", TreeRender::new(&mut t, RenderOptions::new())));
+
+        t.toggle_focus_fold();
+        t.focus_up();
+        t.focus_left();
+        t.toggle_focus_fold();
+        t.focus_right();
+        t.focus_right();
+        t.toggle_focus_fold();
+        assert!(!t.is_partial());
+
+        let mut full = Tree::new_from_dir(&test_dir("")).unwrap();
+        assert_eq!(full.total_size(), t.total_size());
+        assert_eq!(
+            format!("{}", TreeRender::new(&mut full, RenderOptions::new())),
+            format!("{}", TreeRender::new(&mut t, RenderOptions::new()))
+        );
+    }
+
+    #[test]
+    fn test_lazy_missing() {
+        use options::FsOptions;
+        use std::fs::{create_dir_all, remove_dir};
+        use util::TempDir;
+
+        let dir = TempDir::new("lazy-missing");
+        create_dir_all(dir.join("a/b")).unwrap();
+        create_dir_all(dir.join("c")).unwrap();
+
+        let mut opts = FsOptions::new(&dir);
+        opts.lazy_depth(Some(1));
+        let mut t = Tree::new_with_options(opts).unwrap();
+        assert_eq!("a", t.focused().name);
+
+        remove_dir(dir.join("c")).unwrap();
+        t.focus_right();
+        assert_eq!("c", t.focused().name);
+        assert_eq!(1, t.errors().len());
+
+        t.focus_down();
+        t.toggle_focus_fold();
+        assert_eq!(1, t.errors().len());
+    }
+
+    #[test]
+    fn test_lazy_filter() {
+        use options::FsOptions;
+        use search::MatchKind;
+
+        let mut opts = FsOptions::new(test_dir(""));
+        opts.lazy_depth(Some(1));
+        let mut t = Tree::new_with_options(opts).unwrap();
+        t.filter(Some(&Matcher::new("{simple,myfile}", MatchKind::Glob).unwrap()));
+        assert_eq!("simple", t.focused().name);
+
+        t.toggle_focus_fold();
+        let exp = format!(
+            "{}\n{} {}\n{}{} {}\n\n{}\n",
+            "resources/test",
+            END_BRANCH,
+            "simple",
+            BLANK_INDENT,
+            END_BRANCH,
+            "myfile",
+            "1 directory, 1 file (partial)",
+        );
+        assert_eq!(exp, format!("{}", TreeRender::new(&mut t, RenderOptions::new())));
+    }
+
+    #[test]
+    fn test_scan() {
+        use options::FsOptions;
+
+        let mut t = Tree::new_scanning(FsOptions::new(test_dir(""))).unwrap();
+        while t.is_scanning() {
+            t.poll_scan();
+            ::std::thread::sleep(::std::time::Duration::from_millis(1));
+        }
+        assert!(!t.is_partial());
+        assert_eq!("link", t.focused().name);
+
+        let mut full = Tree::new_from_dir(&test_dir("")).unwrap();
+        assert_eq!(full.total_size(), t.total_size());
+        assert_eq!(
+            format!("{}", TreeRender::new(&mut full, RenderOptions::new())),
+            format!("{}", TreeRender::new(&mut t, RenderOptions::new()))
+        );
+    }
+
+    #[test]
+    fn test_scan_sort() {
+        use options::{FsOptions, SortOrder};
+
+        let sort = SortOrder {
+            reverse: true,
+            ..SortOrder::default()
+        };
+        let mut t = Tree::new_scanning(FsOptions::new(test_dir(""))).unwrap();
+        t.sort(sort);
+        while t.is_scanning() {
+            t.poll_scan();
+            ::std::thread::sleep(::std::time::Duration::from_millis(1));
+        }
+
+        let mut opts = FsOptions::new(test_dir(""));
+        opts.reverse(true);
+        let mut full = Tree::new_with_options(opts).unwrap();
+        assert_eq!(
+            format!("{}", TreeRender::new(&mut full, RenderOptions::new())),
+            format!("{}", TreeRender::new(&mut t, RenderOptions::new()))
+        );
+    }
+
+    #[test]
+    fn test_scan_matches() {
+        use options::FsOptions;
+        use search::MatchKind;
+
+        let mut t = Tree::new_scanning(FsOptions::new(test_dir(""))).unwrap();
+        let mut r = TreeRender::new(&mut t, RenderOptions::new());
+        r.filter(Some(Matcher::new("my", MatchKind::Substring).unwrap()));
+        r.search(Matcher::new("file", MatchKind::Substring).unwrap());
+        while r.tree.is_scanning() {
+            r.poll_scan();
+            ::std::thread::sleep(::std::time::Duration::from_millis(1));
+        }
+
+        let filter_hits = r.filter.as_ref().unwrap().hits.clone();
+        let search_hits = r.search.as_ref().unwrap().hits.clone();
+        assert_eq!(5, filter_hits.len());
+        assert_eq!(4, search_hits.len());
+        r.filter(Some(Matcher::new("my", MatchKind::Substring).unwrap()));
+        assert_eq!(filter_hits, r.filter.as_ref().unwrap().hits);
+        assert_eq!(search_hits, r.search.as_ref().unwrap().hits);
+    }
+
+    #[test]
+    fn test_cancel_scan() {
+        use options::FsOptions;
+
+        let mut t = Tree::new_scanning(FsOptions::new(test_dir(""))).unwrap();
+        t.cancel_scan();
+        assert!(!t.is_scanning());
+        assert!(!t.poll_scan());
+        assert!(t.summary().ends_with(" (partial)"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_watch() {
+        use options::FsOptions;
+        use std::fs::{create_dir, create_dir_all, remove_file, rename, File};
+        use std::time::Duration;
+        use util::TempDir;
+
+        let dir = TempDir::new("render-watch");
+        create_dir_all(dir.join("a")).unwrap();
+        File::create(dir.join("a/x")).unwrap();
+        File::create(dir.join("a/y")).unwrap();
+        File::create(dir.join("b")).unwrap();
+
+        let mut opts = FsOptions::new(&dir);
+        opts.hidden(false);
+        let mut t = Tree::new_with_options(opts).unwrap();
+        t.watch().unwrap();
+        assert!(!t.poll_watch());
+
+        // Remember y as a's focused child, and fold a.
+        t.focus_down();
+        t.focus_right();
+        t.focus_up();
+        t.toggle_focus_fold();
+
+        File::create(dir.join("c")).unwrap();
+        File::create(dir.join(".hidden")).unwrap();
+        rename(dir.join("a"), dir.join("d")).unwrap();
+        remove_file(dir.join("b")).unwrap();
+        create_dir(dir.join("e")).unwrap();
+        File::create(dir.join("e/z")).unwrap();
+
+        let changed = t.poll_watch();
+        assert!(changed);
+
+        let exp = format!(
+            "{}\n{} {}\n{} {}\n{} {}\n{}{} {}\n\n{}\n",
+            dir.display(),
+            MID_BRANCH,
+            "c",
+            MID_BRANCH,
+            "d*",
+            END_BRANCH,
+            "e",
+            BLANK_INDENT,
+            END_BRANCH,
+            "z",
+            "2 directories, 4 files",
+        );
+        assert_eq!(
+            exp,
+            format!("{}", TreeRender::new(&mut t, RenderOptions::new()))
+        );
+
+        assert_eq!("d", t.focused().name);
+        let mut changed = t.changed
+            .keys()
+            .map(|&n| t.tree[n].data.name.to_string_lossy().into_owned())
+            .collect::<Vec<String>>();
+        changed.sort();
+        // Removing b highlights the directory it was in.
+        let mut exp = vec![format!("{}", dir.display())];
+        exp.extend(vec!["c", "d", "e"].into_iter().map(|s| s.to_owned()));
+        assert_eq!(exp, changed);
+        assert!(t.expire_changes(Duration::from_millis(0)));
+        assert!(t.changed.is_empty());
+
+        t.toggle_focus_fold();
+        t.focus_down();
+        assert_eq!("y", t.focused().name);
+    }
+
+    #[test]
+    fn test_refresh() {
+        use options::FsOptions;
+        use std::fs::{create_dir_all, remove_file, File};
+        use util::TempDir;
+
+        let dir = TempDir::new("refresh");
+        create_dir_all(dir.join("a")).unwrap();
+        File::create(dir.join("a/x")).unwrap();
+        File::create(dir.join("a/y")).unwrap();
+        File::create(dir.join("b")).unwrap();
+
+        let mut t = Tree::new_with_options(FsOptions::new(&dir)).unwrap();
+
+        // Remember y as a's focused child.
+        t.focus_down();
+        t.focus_right();
+        t.focus_up();
+
+        File::create(dir.join("a/z")).unwrap();
+        remove_file(dir.join("b")).unwrap();
+        File::create(dir.join("c")).unwrap();
+
+        // Only a is read again.
+        t.refresh_focused();
+        let exp = format!(
+            "{}\n{} {}\n{}{} {}\n{}{} {}\n{}{} {}\n{} {}\n\n{}\n",
+            dir.display(),
+            MID_BRANCH,
+            "a",
+            BAR_INDENT,
+            MID_BRANCH,
+            "x",
+            BAR_INDENT,
+            MID_BRANCH,
+            "y",
+            BAR_INDENT,
+            END_BRANCH,
+            "z",
+            END_BRANCH,
+            "b",
+            "1 directory, 4 files",
+        );
+        assert_eq!(
+            exp,
+            format!("{}", TreeRender::new(&mut t, RenderOptions::new()))
+        );
+        assert_eq!("a", t.focused().name);
+        t.focus_down();
+        assert_eq!("y", t.focused().name);
+
+        // Everything is read again, keeping the focus and folds.
+        t.focus_up();
+        t.toggle_focus_fold();
+        t.rebuild();
+
+        let exp = format!(
+            "{}\n{} {}\n{} {}\n\n{}\n",
+            dir.display(),
+            MID_BRANCH,
+            "a*",
+            END_BRANCH,
+            "c",
+            "1 directory, 4 files",
+        );
+        assert_eq!(
+            exp,
+            format!("{}", TreeRender::new(&mut t, RenderOptions::new()))
+        );
+        assert_eq!("a", t.focused().name);
+        t.toggle_focus_fold();
+        t.focus_down();
+        assert_eq!("y", t.focused().name);
+    }
+
+    #[test]
+    fn test_sort() {
+        use options::{FsOptions, SortBy};
+        use std::fs::{create_dir_all, File};
+        use util::TempDir;
+
+        let dir = TempDir::new("cycle-sort");
+        create_dir_all(dir.join("c")).unwrap();
+        File::create(dir.join("c/x")).unwrap();
+        File::create(dir.join("B")).unwrap();
+        File::create(dir.join("a")).unwrap();
+
+        let mut t = Tree::new_with_options(FsOptions::new(&dir)).unwrap();
+
+        // Fold c, and leave the focus on a.
+        t.focus_right();
+        t.focus_right();
+        t.toggle_focus_fold();
+        t.focus_left();
+        assert_eq!("a", t.focused().name);
+
+        let mut r = TreeRender::new(&mut t, RenderOptions::new());
+        assert_eq!(SortBy::Natural, r.cycle_sort());
+        let exp = format!(
+            "{}\n{} {}\n{} {}\n{} {}\n\n{}\n",
+            dir.display(),
+            MID_BRANCH,
+            "a",
+            MID_BRANCH,
+            "B",
+            END_BRANCH,
+            "c*",
+            "1 directory, 3 files",
+        );
+        assert_eq!(exp, format!("{}", r));
+        assert_eq!("a", r.tree.focused().name);
+        r.focus_right();
+        assert_eq!("B", r.tree.focused().name);
+    }
+
+    #[test]
+    fn test_errors() {
+        use options::FsOptions;
+        use std::fs::create_dir_all;
+        use std::os::unix::fs::symlink;
+        use util::TempDir;
+
+        let dir = TempDir::new("errors");
+        create_dir_all(dir.join("mydir")).unwrap();
+        symlink("..", dir.join("mydir/up")).unwrap();
+        symlink("nowhere", dir.join("broken")).unwrap();
+
+        let mut opts = FsOptions::new(&dir);
+        opts.follow_links(true);
+        let mut t = Tree::new_with_options(opts).unwrap();
+
+        assert_eq!("2 directories, 1 file, 2 errors", t.summary());
+
+        let names = t.errors()
+            .iter()
+            .map(|e| t.tree[e.node].data.name.to_string_lossy().into_owned())
+            .collect::<Vec<String>>();
+        assert!(names.contains(&"up".to_owned()));
+        assert!(names.contains(&"broken".to_owned()));
+
+        let mut r = TreeRender::new(&mut t, RenderOptions::new());
+        let exp = format!(
+            "{}\n{} broken{}nowhere{}\n{} {}\n{}{} up{}..{}\n\n{}\n",
+            dir.display(),
+            MID_BRANCH,
+            LINK_MARK,
+            BROKEN_MARK,
+            END_BRANCH,
+            "mydir",
+            BLANK_INDENT,
+            END_BRANCH,
+            LINK_MARK,
+            LOOP_MARK,
+            "2 directories, 1 file, 2 errors",
+        );
+        assert_eq!(exp, format!("{}", r));
+
+        let mut out = Vec::new();
+        r.render_errors(&mut out, 1, 2, 80).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let bg = Bg(r.opts.bg_color.deref());
+        assert!(out.starts_with("2 errors:\r\n"));
+        assert!(out.contains(&format!("{}  ", bg)));
+
+        let i = names.iter().position(|n| n == "up").unwrap();
+        let node = r.tree.errors()[i].node;
+        r.focus_node(node);
+        assert_eq!("up", r.tree.focused().name);
+    }
+
+    #[test]
+    fn test_search() {
+        use search::MatchKind;
+
+        let mut t = Tree::new_from_dir(&test_dir("")).unwrap();
+        t.focus_right();
+        t.toggle_focus_fold();
+        t.focus_left();
+
+        let mut r = TreeRender::new(&mut t, RenderOptions::new());
+        let parent_name = |r: &TreeRender| {
+            let p = r.tree.tree[r.tree.focused].parent().unwrap();
+            r.tree.tree[p].data.name.clone()
+        };
+
+        r.search(Matcher::new("myfile", MatchKind::Substring).unwrap());
+        assert_eq!(Some(2), r.search_hits());
+        assert_eq!("myfile", r.tree.focused().name);
+        assert_eq!("mydir", parent_name(&r));
+        assert!(!format!("{}", r).contains("one_dir*"));
+
+        r.search_next();
+        assert_eq!("myfile", r.tree.focused().name);
+        assert_eq!("simple", parent_name(&r));
+
+        r.search_next();
+        assert_eq!("mydir", parent_name(&r));
+
+        r.search_prev();
+        assert_eq!("simple", parent_name(&r));
+
+        r.search(Matcher::new("^my.*file$", MatchKind::Regex).unwrap());
+        assert_eq!(Some(4), r.search_hits());
+        assert_eq!("simple", parent_name(&r));
+
+        r.clear_search();
+        assert_eq!(None, r.search_hits());
+    }
+
+    #[test]
+    fn test_search_escaped() {
+        use options::FsOptions;
+        use search::MatchKind;
+        use std::ffi::OsStr;
+        use std::fs::File;
+        use std::os::unix::ffi::OsStrExt;
+        use util::TempDir;
+
+        let dir = TempDir::new("search-escaped");
+        File::create(dir.join(OsStr::from_bytes(b"caf\xe9"))).unwrap();
+        File::create(dir.join("cafe")).unwrap();
+        let mut t = Tree::new_from_dir(&dir).unwrap();
+        let escaped = || Matcher::new("\\xe9", MatchKind::Substring).unwrap();
+
+        // Names are matched as they are shown.
+        let mut r = TreeRender::new(&mut t, RenderOptions::new());
+        r.search(escaped());
+        assert_eq!(Some(0), r.search_hits());
+
+        let mut fs_opts = FsOptions::new(&dir);
+        fs_opts.escape_names(true);
+        let mut t = Tree::new_with_options(fs_opts).unwrap();
+        let mut opts = RenderOptions::new();
+        opts.filter(Some(escaped()));
+        let mut r = TreeRender::new(&mut t, opts);
+        assert_eq!(Some(1), r.filter_hits());
+        r.search(escaped());
+        assert_eq!(Some(1), r.search_hits());
+        assert_eq!(OsStr::from_bytes(b"caf\xe9"), r.tree.focused().name);
+        assert!(format!("{}", r).contains("caf\\xe9\n"));
+    }
+
+    #[test]
+    fn test_filter() {
+        use search::MatchKind;
+
+        let dir = test_dir("");
+        let unfiltered = draw_to_string(&dir);
+
+        let mut t = Tree::new_from_dir(&dir).unwrap();
+        let mut opts = RenderOptions::new();
+        opts.filter(Some(Matcher::new("myfile", MatchKind::Glob).unwrap()));
+        let mut r = TreeRender::new(&mut t, opts);
+
+        let exp = format!(
+            "{}\n{} {}\n{}{} {}\n{}{}{} {}\n{} {}\n{}{} {}\n\n{}\n",
+            "resources/test",
+            MID_BRANCH,
+            "one_dir",
+            BAR_INDENT,
+            END_BRANCH,
+            "mydir",
+            BAR_INDENT,
+            BLANK_INDENT,
+            END_BRANCH,
+            "myfile",
+            END_BRANCH,
+            "simple",
+            BLANK_INDENT,
+            END_BRANCH,
+            "myfile",
+            "3 directories, 2 files",
+        );
+
+        assert_eq!(exp, format!("{}", r));
+        assert_eq!(Some(2), r.filter_hits());
+        assert_eq!("one_dir", r.tree.focused().name);
+
+        r.focus_right();
+        assert_eq!("simple", r.tree.focused().name);
+        r.focus_right();
+        assert_eq!("simple", r.tree.focused().name);
+        r.focus_down();
+        assert_eq!("myfile", r.tree.focused().name);
+        r.focus_up();
+
+        r.toggle_focus_fold();
+        let exp_folded = format!(
+            "{}\n{} {}\n{}{} {}\n{}{}{} {}\n{} {}\n\n{}\n",
+            "resources/test",
+            MID_BRANCH,
+            "one_dir",
+            BAR_INDENT,
+            END_BRANCH,
+            "mydir",
+            BAR_INDENT,
+            BLANK_INDENT,
+            END_BRANCH,
+            "myfile",
+            END_BRANCH,
+            "simple*",
+            "3 directories, 2 files",
+        );
+        assert_eq!(exp_folded, format!("{}", r));
+
+        r.toggle_focus_fold();
+        assert_eq!(exp, format!("{}", r));
+
+        r.filter(None);
+        assert_eq!(unfiltered, format!("{}", r));
+        assert_eq!(None, r.filter_hits());
+    }
+
     #[test]
     fn test_fold() {
-        let mut t = Tree::new_from_dir(&test_dir(""));
+        let mut t = Tree::new_from_dir(&test_dir("")).unwrap();
         t.focus_right();
         t.focus_down();
         t.toggle_focus_fold();
@@ -0,0 +1,103 @@
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+
+/// The ways in which a search pattern can be interpreted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MatchKind {
+    Substring,
+    Glob,
+    Regex,
+}
+
+impl MatchKind {
+    /// The kind following this one, for cycling through kinds interactively.
+    pub fn next(self) -> Self {
+        match self {
+            MatchKind::Substring => MatchKind::Glob,
+            MatchKind::Glob => MatchKind::Regex,
+            MatchKind::Regex => MatchKind::Substring,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MatchKind::Substring => "substring",
+            MatchKind::Glob => "glob",
+            MatchKind::Regex => "regex",
+        }
+    }
+}
+
+/// A compiled pattern against which file names can be matched.
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    Substring(String),
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl Matcher {
+    pub fn new(pattern: &str, kind: MatchKind) -> Result<Self, String> {
+        match kind {
+            MatchKind::Substring => Ok(Matcher::Substring(pattern.to_owned())),
+            MatchKind::Glob => Glob::new(pattern)
+                .map(|g| Matcher::Glob(g.compile_matcher()))
+                .map_err(|e| format!("{}", e)),
+            MatchKind::Regex => Regex::new(pattern)
+                .map(Matcher::Regex)
+                .map_err(|e| format!("{}", e)),
+        }
+    }
+
+    /// Find the byte range of `name` matched by the pattern, if any.
+    ///
+    /// Globs match whole names, so their range is always all of `name`.
+    pub fn find(&self, name: &str) -> Option<(usize, usize)> {
+        match self {
+            Matcher::Substring(s) => name.find(s.as_str()).map(|i| (i, i + s.len())),
+            Matcher::Glob(g) => {
+                if g.is_match(name) {
+                    Some((0, name.len()))
+                } else {
+                    None
+                }
+            }
+            Matcher::Regex(r) => r.find(name).map(|m| (m.start(), m.end())),
+        }
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substring() {
+        let m = Matcher::new("other", MatchKind::Substring).unwrap();
+        assert_eq!(Some((2, 7)), m.find("myotherfile"));
+        assert_eq!(None, m.find("myfile"));
+    }
+
+    #[test]
+    fn test_glob() {
+        let m = Matcher::new("my*file", MatchKind::Glob).unwrap();
+        assert_eq!(Some((0, 6)), m.find("myfile"));
+        assert_eq!(Some((0, 11)), m.find("myotherfile"));
+        assert_eq!(None, m.find("mydir"));
+
+        assert!(Matcher::new("[", MatchKind::Glob).is_err());
+    }
+
+    #[test]
+    fn test_regex() {
+        let m = Matcher::new("o.*f", MatchKind::Regex).unwrap();
+        assert_eq!(Some((2, 8)), m.find("myotherfile"));
+        assert!(!m.is_match("mydir"));
+
+        assert!(Matcher::new("(", MatchKind::Regex).is_err());
+    }
+}
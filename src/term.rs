@@ -1,80 +1,424 @@
-use std::io;
+use std::cmp;
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
 
+use libc;
 use termion;
 use termion::clear::All;
 use termion::cursor::{Goto, Hide, Show};
 use termion::event::Key;
-use termion::input::TermRead;
-use termion::raw::IntoRawMode;
+use termion::raw::{IntoRawMode, RawTerminal};
 use termion::screen::{ToAlternateScreen, ToMainScreen};
 
+use indextree::NodeId;
+
+use config::{Action, KeyMap};
+use input::{Event, Input};
 use render::TreeRender;
+use search::{MatchKind, Matcher};
+
+/// How often to add newly read entries to the tree while it is being built,
+/// and apply changes to the FS while it is being watched.
+const POLL_MS: u64 = 100;
+
+/// How long to highlight entries changed while watching the FS.
+const CHANGE_HIGHLIGHT_MS: u64 = 1500;
+
+/// What a prompt's pattern is used for.
+enum PromptMode {
+    Search,
+    /// Filtering, remembering the filter that was active before the prompt opened.
+    Filter(Option<Matcher>),
+}
+
+/// The state of the search or filter prompt, while it is open.
+struct Prompt {
+    mode: PromptMode,
+    kind: MatchKind,
+    input: String,
+    /// The node focused when the prompt was opened, to return to on cancel.
+    origin: NodeId,
+    error: Option<String>,
+}
+
+impl Prompt {
+    fn search(render: &TreeRender) -> Self {
+        Prompt::new(PromptMode::Search, render)
+    }
+
+    fn filter(render: &TreeRender) -> Self {
+        Prompt::new(PromptMode::Filter(render.filter_matcher().cloned()), render)
+    }
+
+    fn new(mode: PromptMode, render: &TreeRender) -> Self {
+        Self {
+            mode,
+            kind: MatchKind::Substring,
+            input: String::new(),
+            origin: render.tree.focused,
+            error: None,
+        }
+    }
+
+    /// Re-run the search or filter for the current input.
+    fn update(&mut self, render: &mut TreeRender) {
+        self.error = None;
+        if self.input.is_empty() {
+            match self.mode {
+                PromptMode::Search => render.clear_search(),
+                PromptMode::Filter(_) => render.filter(None),
+            }
+            return;
+        }
+
+        match Matcher::new(&self.input, self.kind) {
+            Ok(m) => match self.mode {
+                PromptMode::Search => render.search(m),
+                PromptMode::Filter(_) => render.filter(Some(m)),
+            },
+            Err(e) => self.error = Some(e),
+        }
+    }
 
-fn clear() {
-    print!("{}", All);
-    print!("{}", Goto(1, 1));
+    /// Undo the effects of the prompt.
+    fn cancel(self, render: &mut TreeRender) {
+        match self.mode {
+            PromptMode::Search => render.clear_search(),
+            PromptMode::Filter(previous) => render.filter(previous),
+        }
+        render.tree.focus_node(self.origin);
+    }
+
+    fn line(&self, render: &TreeRender) -> String {
+        let (sigil, hits) = match self.mode {
+            PromptMode::Search => ("/", render.search_hits()),
+            PromptMode::Filter(_) => ("&", render.filter_hits()),
+        };
+
+        let status = match (&self.error, hits) {
+            (Some(e), _) => format!("  (invalid {}: {})", self.kind.name(), e),
+            (None, Some(1)) => "  (1 match)".to_owned(),
+            (None, Some(n)) => format!("  ({} matches)", n),
+            (None, None) => String::new(),
+        };
+
+        format!("{}({}) {}{}", sigil, self.kind.name(), self.input, status)
+    }
+}
+
+fn clear(tty: &mut File) -> io::Result<()> {
+    write!(tty, "{}{}", All, Goto(1, 1))
+}
+
+/// The size of the terminal, in columns and rows.
+///
+/// This asks the terminal itself, since stdout may have been redirected.
+fn terminal_size(tty: &File) -> io::Result<(u16, u16)> {
+    unsafe {
+        let mut size: libc::winsize = ::std::mem::zeroed();
+        if libc::ioctl(tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((size.ws_col, size.ws_row))
+    }
+}
+
+/// The list of errors reading the tree, while it is open.
+struct ErrorPanel {
+    selected: usize,
+}
+
+impl ErrorPanel {
+    /// How many rows the panel takes up on a screen `height` rows tall.
+    fn rows(render: &TreeRender, height: usize) -> usize {
+        (render.tree.errors().len() + 1).min(cmp::max(height / 3, 2))
+    }
 }
 
-fn render_to_stdout(render: &TreeRender) -> io::Result<()> {
-    let mut stdout = io::stdout();
+/// Render the tree to the terminal, with an optional error panel and status
+/// line (e.g. a prompt) beneath it.
+fn render_to_tty(
+    tty: &mut File,
+    render: &TreeRender,
+    status: Option<&str>,
+    panel: Option<&ErrorPanel>,
+) -> io::Result<()> {
+    clear(tty)?;
+    let (x, y) = terminal_size(tty)?;
+
+    let status_rows = if status.is_some() { 1 } else { 0 };
+    let panel_rows = panel.map_or(0, |_| ErrorPanel::rows(render, y as usize));
+    let tree_rows = (y as usize).saturating_sub(status_rows + panel_rows);
+    render.render_around_focus(tty, tree_rows, x as usize)?;
 
-    clear();
-    let (x, y) = termion::terminal_size()?;
+    if let Some(p) = panel {
+        write!(tty, "{}", Goto(1, tree_rows as u16 + 1))?;
+        render.render_errors(tty, p.selected, panel_rows, x as usize)?;
+    }
+    if let Some(line) = status {
+        write!(tty, "{}{}", Goto(1, y), line)?;
+    }
 
-    render.render_around_focus(&mut stdout, y as usize, x as usize)
+    tty.flush()
 }
 
-pub fn navigate(render: &mut TreeRender) {
+/// A status line showing how much of the tree has been read so far.
+fn scan_status(render: &TreeRender) -> Option<String> {
+    if render.tree.is_scanning() {
+        Some(format!(
+            "scanning: {} dirs, {} files",
+            render.tree.n_dirs, render.tree.n_files
+        ))
+    } else {
+        None
+    }
+}
+
+/// The user's preferred editor.
+fn editor() -> String {
+    env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_owned())
+}
+
+/// The user's preferred pager.
+fn pager() -> String {
+    env::var("PAGER").unwrap_or_else(|_| "less".to_owned())
+}
+
+/// Open the focused entry with `program`, which may include arguments
+/// (e.g. `code --wait`).
+///
+/// Raw mode and the alternate screen are suspended while the program runs,
+/// so that it has the terminal to itself, even if stdout is redirected.
+/// Returns a message to show the user if the program could not be run.
+fn open_focused(
+    raw: &mut Option<RawTerminal<File>>,
+    tty: &mut File,
+    program: &str,
+    render: &TreeRender,
+) -> Option<String> {
+    let path = render.tree.focused().de.path().to_owned();
+
+    let mut words = program.split_whitespace();
+    let cmd = match words.next() {
+        Some(cmd) => cmd,
+        None => return Some("No program to open the file with".to_owned()),
+    };
+
+    write!(tty, "{}{}", Show, ToMainScreen).unwrap();
+    tty.flush().unwrap();
+    raw.take();
+
+    let stdout = tty.try_clone().map(Stdio::from).unwrap_or_else(|_| Stdio::inherit());
+    let res = Command::new(cmd).args(words).arg(&path).stdout(stdout).status();
+
+    *raw = Some(tty.try_clone().unwrap().into_raw_mode().unwrap());
+    write!(tty, "{}{}", ToAlternateScreen, Hide).unwrap();
+
+    match res {
+        Ok(status) if status.success() => None,
+        Ok(status) => Some(format!("`{}` exited with {}", program, status)),
+        Err(e) => Some(format!("Could not run `{}`: {}", program, e)),
+    }
+}
+
+/// Handle a key pressed while a prompt is open.
+///
+/// Returns the prompt if it should stay open.
+fn prompt_key(mut prompt: Prompt, render: &mut TreeRender, key: Key) -> Option<Prompt> {
+    match key {
+        Key::Char('\n') => return None,
+        Key::Esc | Key::Ctrl('c') => {
+            prompt.cancel(render);
+            return None;
+        }
+        Key::Char('\t') => {
+            prompt.kind = prompt.kind.next();
+        }
+        Key::Backspace => {
+            prompt.input.pop();
+        }
+        Key::Char(c) => {
+            prompt.input.push(c);
+        }
+        _ => return Some(prompt),
+    }
+
+    prompt.update(render);
+    Some(prompt)
+}
+
+/// Handle a key pressed while the error panel is open.
+///
+/// Returns the panel if it should stay open.
+fn panel_key(
+    mut panel: ErrorPanel,
+    render: &mut TreeRender,
+    keys: &KeyMap,
+    key: Key,
+) -> Option<ErrorPanel> {
+    match keys.get(&key) {
+        Some(Action::FocusLeft) => {
+            panel.selected = panel.selected.saturating_sub(1);
+        }
+        Some(Action::FocusRight) if panel.selected + 1 < render.tree.errors().len() => {
+            panel.selected += 1;
+        }
+        Some(Action::Choose) => {
+            let node = render.tree.errors()[panel.selected].node;
+            render.focus_node(node);
+            return None;
+        }
+        Some(Action::Errors) | Some(Action::Quit) => return None,
+        _ => {}
+    }
+
+    Some(panel)
+}
+
+/// Interactively navigate the tree until the user exits.
+///
+/// If the tree is still being built, entries are added as they are read, and
+/// the build is cancelled if the user exits before it finishes.
+///
+/// Returns the absolute path of the focused entry if the user exited by choosing
+/// it with Enter, or `None` if they quit.
+///
+/// The navigator is drawn on the terminal directly, leaving stdout for the
+/// chosen path, so that it can be captured with e.g. `dir=$(itree)`.
+pub fn navigate(render: &mut TreeRender, keys: &KeyMap) -> Option<PathBuf> {
+    let mut chosen = None;
+    let mut tty = termion::get_tty().unwrap();
     {
         // The following is necessary to properly read from stdin.
         // For details, see: https://github.com/ticki/termion/issues/42
         //
         // Wrapped in block so cleanup printing happens in non-raw mode.
-        let _stdout = io::stdout().into_raw_mode().unwrap();
+        let mut raw = Some(tty.try_clone().unwrap().into_raw_mode().unwrap());
 
-        println!("{}", ToAlternateScreen);
-        println!("{}", Hide);
+        writeln!(tty, "{}", ToAlternateScreen).unwrap();
+        writeln!(tty, "{}", Hide).unwrap();
 
-        render_to_stdout(&render)
+        let mut input = Input::new();
+        if render.tree.is_scanning() || render.tree.is_watching() {
+            input.set_tick(Some(Duration::from_millis(POLL_MS)));
+        }
+
+        render.poll_scan();
+        render_to_tty(&mut tty, render, scan_status(render).as_deref(), None)
             .map_err(|e| {
-                println!("{}", Show);
+                let _ = writeln!(tty, "{}", Show);
                 format!("Failed to render tree: {:?}", e)
             })
             .unwrap();
 
-        let mut keys = io::stdin().keys();
-        while let Some(Ok(key)) = keys.next() {
-            match key {
-                Key::Left | Key::Char('h') => {
-                    render.focus_up();
-                }
-                Key::Right | Key::Char('l') => {
-                    render.focus_down();
-                }
-                Key::Up | Key::Char('k') => {
-                    render.focus_left();
-                }
-                Key::Down | Key::Char('j') => {
-                    render.focus_right();
+        let mut prompt: Option<Prompt> = None;
+        let mut panel: Option<ErrorPanel> = None;
+        let mut message: Option<String> = None;
+        while let Ok(Some(event)) = input.next_event() {
+            match event {
+                // Nothing to do but redraw, at the new size.
+                Event::Resize => {}
+                Event::Tick => {
+                    let scanned = render.poll_scan();
+                    let watched = render.poll_watch();
+                    let expired = render
+                        .tree
+                        .expire_changes(Duration::from_millis(CHANGE_HIGHLIGHT_MS));
+                    if !render.tree.is_scanning() && !render.tree.is_watching() {
+                        input.set_tick(None);
+                    }
+                    if !(scanned || watched || expired) {
+                        continue;
+                    }
+
+                    // Errors may have gone with the entries they were about.
+                    let n_errors = render.tree.errors().len();
+                    panel = panel.filter(|_| n_errors > 0).map(|p| ErrorPanel {
+                        selected: p.selected.min(n_errors.saturating_sub(1)),
+                    });
                 }
-                Key::Char('f') => {
-                    render.toggle_focus_fold();
+                Event::Key(key) => {
+                    message = None;
+                    if let Some(p) = prompt.take() {
+                        prompt = prompt_key(p, render, key);
+                    } else if let Some(p) = panel.take() {
+                        panel = panel_key(p, render, keys, key);
+                    } else {
+                        match keys.get(&key) {
+                            Some(Action::FocusUp) => render.focus_up(),
+                            Some(Action::FocusDown) => render.focus_down(),
+                            Some(Action::FocusLeft) => render.focus_left(),
+                            Some(Action::FocusRight) => render.focus_right(),
+                            Some(Action::Fold) => render.toggle_focus_fold(),
+                            Some(Action::Search) => prompt = Some(Prompt::search(render)),
+                            Some(Action::Filter) => prompt = Some(Prompt::filter(render)),
+                            Some(Action::SearchNext) => render.search_next(),
+                            Some(Action::SearchPrev) => render.search_prev(),
+                            Some(Action::Edit) => {
+                                message = open_focused(&mut raw, &mut tty, &editor(), render);
+                            }
+                            Some(Action::View) => {
+                                message = open_focused(&mut raw, &mut tty, &pager(), render);
+                            }
+                            Some(Action::Errors) => {
+                                if render.tree.errors().is_empty() {
+                                    message = Some("No errors reading the tree".to_owned());
+                                } else {
+                                    panel = Some(ErrorPanel { selected: 0 });
+                                }
+                            }
+                            Some(Action::Refresh) => render.refresh_focused(),
+                            Some(Action::Rebuild) => render.rebuild(),
+                            Some(Action::Sort) => {
+                                let by = render.cycle_sort();
+                                message = Some(format!("Sorted by {}", by.name()));
+                            }
+                            Some(Action::ScrollLeft) => render.scroll_left(),
+                            Some(Action::ScrollRight) => render.scroll_right(),
+                            Some(Action::Choose) => {
+                                chosen = Some(render.tree.focused_path());
+                                break;
+                            }
+                            Some(Action::Quit) => break,
+                            None => {}
+                        }
+                    }
                 }
-                Key::Esc | Key::Char('q') | Key::Ctrl('c') => break,
-                _ => {}
             }
 
-            render_to_stdout(&render)
+            let status = prompt
+                .as_ref()
+                .map(|p| p.line(render))
+                .or_else(|| message.clone())
+                .or_else(|| scan_status(render));
+            render_to_tty(&mut tty, render, status.as_deref(), panel.as_ref())
                 .map_err(|e| {
-                    println!("{}", Show);
+                    let _ = writeln!(tty, "{}", Show);
                     format!("Failed to render tree: {:?}", e)
                 })
                 .unwrap();
         }
     }
 
-    println!("{}", Show);
-    println!("{}", ToMainScreen);
+    render.tree.cancel_scan();
+
+    writeln!(tty, "{}", Show).unwrap();
+    writeln!(tty, "{}", ToMainScreen).unwrap();
+
+    // The chosen path is all that belongs on stdout, for scripts to read.
+    if chosen.is_none() {
+        println!("{}", render.summary());
+    }
 
-    println!("{}", render.tree.summary());
+    chosen.map(|path| {
+        path.map_err(|e| format!("Failed to get the path of the focused entry: {:?}", e))
+            .unwrap()
+    })
 }
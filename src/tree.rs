@@ -1,10 +1,21 @@
+use std::cmp;
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use indextree::{Arena, NodeId};
 
-use fs::{fs_to_tree, FileType, FsEntry};
+use error::ItreeError;
+use fs::{
+    absolute_path, aggregate_sizes, compare_entries, counts_as_dir, escape_name, fs_to_tree,
+    load_dir, load_entry, sort_children, start_scan, Added, EntryError, FileType, FsEntry,
+    LinkKind, Scan,
+};
 use options::*;
+use search::Matcher;
+use watch::{Change, Watcher};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum PrefixPiece {
@@ -26,7 +37,11 @@ pub struct TreeLine {
 pub struct TreeLines {
     pub(crate) inds: HashMap<NodeId, usize>,
     pub(crate) lines: Vec<TreeLine>,
-    pub(crate) folded: HashSet<usize>,
+    /// The folded directories, by node, so that they stay folded however
+    /// their lines move.
+    pub(crate) folded: HashSet<NodeId>,
+    /// The lines left visible by the active filter, or `None` if there is no filter.
+    pub(crate) kept: Option<HashSet<usize>>,
     pub(crate) count: usize,
 }
 
@@ -36,6 +51,7 @@ impl TreeLines {
             inds: HashMap::new(),
             lines: Vec::new(),
             folded: HashSet::new(),
+            kept: None,
             count: 0,
         }
     }
@@ -54,6 +70,53 @@ impl TreeLines {
         });
         self.count += 1;
     }
+
+    /// Insert `new`, as drawn by themselves, before line `at`, shifting every
+    /// line from `at` onward.
+    ///
+    /// The inserted lines are linked to each other as though all were visible,
+    /// and the first to the line before them, but the lines around them
+    /// should be relinked afterward.
+    fn insert(&mut self, at: usize, mut new: Vec<TreeLine>) {
+        let k = new.len();
+        let shift = |i: usize| if i >= at { i + k } else { i };
+
+        for ind in self.inds.values_mut() {
+            *ind = shift(*ind);
+        }
+        for line in self.lines.iter_mut() {
+            line.prev = line.prev.map(shift);
+            line.next = shift(line.next);
+        }
+        if let Some(kept) = self.kept.take() {
+            self.kept = Some(kept.into_iter().map(shift).collect());
+        }
+
+        for (i, line) in new.iter_mut().enumerate() {
+            line.prev = Some(line.prev.map_or(at - 1, |p| p + at));
+            line.next += at;
+            self.inds.insert(line.node, at + i);
+        }
+        self.lines.splice(at..at, new);
+        self.count += k;
+    }
+}
+
+/// Fold and filter state, remembered by node so that it survives redrawing
+/// the lines after the tree changes shape.
+#[derive(Debug)]
+struct LineState {
+    folded: HashSet<NodeId>,
+    kept: Option<HashSet<NodeId>>,
+}
+
+/// The focus, fold state and focused children beneath an entry, by path
+/// relative to it, so that they survive it being read again.
+#[derive(Debug, Default)]
+struct PathState {
+    focused: Option<PathBuf>,
+    folded: Vec<PathBuf>,
+    focused_child: Vec<(PathBuf, PathBuf)>,
 }
 
 #[derive(Debug)]
@@ -63,28 +126,58 @@ pub struct Tree {
     pub(crate) focused: NodeId,
     pub(crate) focused_child: HashMap<NodeId, NodeId>,
     pub(crate) lines: TreeLines,
+    /// The pattern the lines are filtered by, if any, to apply to entries read later.
+    filter: Option<Matcher>,
     pub(crate) n_files: usize,
     pub(crate) n_dirs: usize,
+    /// Directories whose children have not been read yet.
+    pub(crate) unread: HashSet<NodeId>,
+    /// Directories read when the cursor reached them, which are still folded
+    /// only because they had not been read before, so moving into them unfolds them.
+    unopened: HashSet<NodeId>,
+    /// Whether entries have been read, or the tree otherwise changed, since
+    /// the flag was last cleared.
+    pub(crate) loaded_more: bool,
+    /// Entries added by the scan since the list was last taken, for searches
+    /// to match without going through the whole tree again.
+    pub(crate) scanned: Vec<NodeId>,
+    /// The walk still adding entries to the tree, if any.
+    scan: Option<Scan>,
+    /// Whether a walk was cancelled before it finished, leaving the tree incomplete.
+    cancelled: bool,
+    /// Errors reading entries beneath the root, in the order they were found.
+    pub(crate) errors: Vec<EntryError>,
+    /// Watches the FS for changes to apply to the tree, if it is being watched.
+    watcher: Option<Watcher>,
+    /// Entries changed by the FS being watched, and when.
+    pub(crate) changed: HashMap<NodeId, Instant>,
+    options: FsOptions<PathBuf>,
 }
 
 impl Tree {
     #[allow(dead_code)]
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self, ItreeError> {
         Tree::new_with_options(FsOptions::new("."))
     }
 
     #[allow(dead_code)]
-    pub fn new_from_dir<P: AsRef<Path>>(dir: &P) -> Self {
+    pub fn new_from_dir<P: AsRef<Path>>(dir: &P) -> Result<Self, ItreeError> {
         let opt = FsOptions::new(dir);
         Tree::new_with_options(opt)
     }
 
-    pub fn new_with_options<P: AsRef<Path>>(options: FsOptions<P>) -> Self {
-        let (tree, root, n_files, n_dirs) = fs_to_tree(&options);
+    /// Build a tree, reading the FS as described by `options`.
+    ///
+    /// Fails if the root can't be read, or an ignore pattern can't be parsed.
+    /// Errors reading anything beneath the root are kept in `errors`.
+    pub fn new_with_options<P: AsRef<Path>>(options: FsOptions<P>) -> Result<Self, ItreeError> {
+        let (mut tree, root, added) = fs_to_tree(&options)?;
+        aggregate_sizes(&mut tree, root);
 
-        let lines = Tree::draw(&tree, root);
+        let mut lines = Tree::draw(&tree, root);
+        lines.folded.extend(added.unread.iter().cloned());
 
-        Self {
+        Ok(Self {
             focused: if let Some(c) = tree[root].first_child() {
                 c
             } else {
@@ -94,27 +187,756 @@ impl Tree {
             root,
             focused_child: HashMap::new(),
             lines,
-            n_files,
-            n_dirs,
+            filter: None,
+            n_files: added.n_files,
+            n_dirs: added.n_dirs,
+            unread: added.unread.into_iter().collect(),
+            unopened: HashSet::new(),
+            loaded_more: false,
+            scanned: Vec::new(),
+            scan: None,
+            cancelled: false,
+            errors: added.errors,
+            watcher: None,
+            changed: HashMap::new(),
+            options: options.with_root(options.root.as_ref().to_owned()),
+        })
+    }
+
+    /// Start building a tree, reading the FS on a background thread.
+    ///
+    /// Only the root is in the tree at first. The rest is added as it is
+    /// read, each time `poll_scan` is called.
+    ///
+    /// Fails if the root can't be read, or an ignore pattern can't be parsed.
+    pub fn new_scanning<P: AsRef<Path>>(options: FsOptions<P>) -> Result<Self, ItreeError> {
+        let (mut tree, root, scan) = start_scan(&options)?;
+        aggregate_sizes(&mut tree, root);
+        let lines = Tree::draw(&tree, root);
+
+        Ok(Self {
+            focused: root,
+            tree,
+            root,
+            focused_child: HashMap::new(),
+            lines,
+            filter: None,
+            n_files: 0,
+            n_dirs: 0,
+            unread: HashSet::new(),
+            unopened: HashSet::new(),
+            loaded_more: false,
+            scanned: Vec::new(),
+            scan: Some(scan),
+            cancelled: false,
+            errors: Vec::new(),
+            watcher: None,
+            changed: HashMap::new(),
+            options: options.with_root(options.root.as_ref().to_owned()),
+        })
+    }
+
+    /// Whether the FS is still being read on a background thread.
+    pub fn is_scanning(&self) -> bool {
+        self.scan.is_some()
+    }
+
+    /// Add the entries read by the scan since the last poll to the tree.
+    ///
+    /// Returns whether the tree changed, or the scan finished.
+    pub fn poll_scan(&mut self) -> bool {
+        let (added, finished) = match self.scan.as_mut() {
+            Some(scan) => (scan.poll(&mut self.tree), scan.is_finished()),
+            None => return false,
+        };
+
+        if finished {
+            self.scan = None;
+            let root = self.root;
+            self.watch_dirs(root);
         }
+        self.errors.extend(added.errors);
+        if added.placed.is_empty() {
+            return finished;
+        }
+
+        self.n_files += added.n_files;
+        self.n_dirs += added.n_dirs;
+        self.add_sizes(&added.placed);
+        self.lines.folded.extend(added.unread.iter().cloned());
+        self.unread.extend(added.unread);
+        self.draw_placed(&added.placed);
+        self.scanned.extend(added.placed);
+
+        if self.focused == self.root {
+            let root = self.root;
+            if let Some(c) = root.children(&self.tree).find(|&c| self.is_kept(c)) {
+                self.focused = c;
+            }
+        }
+
+        true
     }
 
-    #[cfg(test)]
-    pub fn focused<'a>(&'a self) -> &'a FsEntry {
-        &self.tree[self.focused].data
+    /// Add the sizes of `placed`, entries just added to the tree, to their
+    /// own totals and those of the directories above them.
+    fn add_sizes(&mut self, placed: &[NodeId]) {
+        for &n in placed {
+            self.tree[n].data.total_size = self.tree[n].data.meta.map_or(0, |m| m.size);
+        }
+        for &n in placed {
+            let size = self.tree[n].data.meta.map_or(0, |m| m.size);
+            let ancestors = n.ancestors(&self.tree).skip(1).collect::<Vec<NodeId>>();
+            for a in ancestors {
+                self.tree[a].data.total_size += size;
+            }
+        }
     }
 
-    fn line_for_node_mut(&mut self, node: NodeId) -> &mut TreeLine {
-        &mut self.lines.lines[self.lines.inds[&node]]
+    /// Add lines for `placed`, entries just added to the tree, splicing each
+    /// run of new siblings in beneath their parent's line, so that only the
+    /// lines around them change.
+    ///
+    /// While a filter is active, the new matches are kept, which can keep
+    /// their ancestors too, so every line is relinked.
+    fn draw_placed(&mut self, placed: &[NodeId]) {
+        let new = placed.iter().cloned().collect::<HashSet<NodeId>>();
+
+        // The new entries whose parents already have lines. The deepest go
+        // first, so that the lines beneath every older sibling are complete
+        // by the time a run is put after them.
+        let mut parents = placed
+            .iter()
+            .filter_map(|&n| self.tree[n].parent())
+            .filter(|p| !new.contains(p))
+            .collect::<HashSet<NodeId>>()
+            .into_iter()
+            .map(|p| (p.ancestors(&self.tree).count(), p))
+            .collect::<Vec<(usize, NodeId)>>();
+        parents.sort_by_key(|&(depth, _)| cmp::Reverse(depth));
+
+        for (_, parent) in parents {
+            let mut run = Vec::new();
+            let mut prev = None;
+            for child in parent.children(&self.tree).collect::<Vec<NodeId>>() {
+                if new.contains(&child) {
+                    run.push(child);
+                    continue;
+                }
+                if !run.is_empty() {
+                    self.insert_run(parent, prev, &run, false);
+                    run.clear();
+                }
+                prev = Some(child);
+            }
+            if !run.is_empty() {
+                self.insert_run(parent, prev, &run, true);
+            }
+        }
+
+        if let Some(m) = self.filter.take() {
+            for &n in placed {
+                if self.is_match(&m, n) {
+                    self.keep_with_ancestors(n);
+                }
+            }
+            self.filter = Some(m);
+            self.redraw_prefixes();
+            self.relink();
+        }
+    }
+
+    /// Insert the lines for `run`, new siblings beneath `parent`, after the
+    /// lines beneath `prev`, their older sibling, if any, and link them in
+    /// with the lines around them.
+    fn insert_run(&mut self, parent: NodeId, prev: Option<NodeId>, run: &[NodeId], last: bool) {
+        let mut indents = self.child_indents(parent);
+        let mut new_lines = TreeLines::new();
+        for &n in run {
+            Tree::draw_node(&mut new_lines, &self.tree, n, &mut indents);
+        }
+
+        let p_ind = self.lines.inds[&parent];
+        let at = match prev {
+            Some(s) => self.last_line_beneath(s) + 1,
+            None => p_ind + 1,
+        };
+        let k = new_lines.count;
+        self.lines.insert(at, new_lines.lines);
+        if self.lines.kept.is_some() {
+            return;
+        }
+
+        // Lines that used to lead past `prev` now lead to the run, and the
+        // line after the run leads back to it. The lines beneath folded
+        // directories are linked too, as though they were unfolded.
+        let before = match prev {
+            Some(s) => {
+                let mut ptr = s;
+                let mut folded = None;
+                loop {
+                    let ind = self.lines.inds[&ptr];
+                    if self.lines.lines[ind].next == at + k {
+                        self.lines.lines[ind].next = at;
+                    }
+                    if folded.is_none() && self.is_folded(ptr) {
+                        folded = Some(ind);
+                    }
+                    match self.tree[ptr].last_child() {
+                        Some(c) => ptr = c,
+                        None => break,
+                    }
+                }
+                folded.unwrap_or(self.lines.inds[&ptr])
+            }
+            None => {
+                if !self.is_folded(parent) {
+                    self.lines.lines[p_ind].next = at;
+                }
+                p_ind
+            }
+        };
+        self.lines.lines[at].prev = Some(before);
+
+        let after = at + k;
+        if after < self.lines.count {
+            let leads_back = match self.lines.lines[after].prev {
+                Some(i) if !last => i >= p_ind,
+                Some(i) => i >= p_ind && !self.is_folded(parent),
+                None => false,
+            };
+            if leads_back {
+                self.lines.lines[after].prev = Some(after - 1);
+            }
+        }
+
+        // `prev` is no longer the last child, so it and the lines beneath it
+        // branch on.
+        if let (true, Some(s)) = (last, prev) {
+            let d = self.lines.lines[self.lines.inds[&s]].prefix.len() - 1;
+            for n in s.descendants(&self.tree).collect::<Vec<NodeId>>() {
+                self.line_for_node_mut(n).prefix[d] = if n == s {
+                    PrefixPiece::MidBranch
+                } else {
+                    PrefixPiece::BarIndent
+                };
+            }
+        }
+    }
+
+    /// The index of the last line beneath `node`, or of its own line if it
+    /// has no children.
+    fn last_line_beneath(&self, node: NodeId) -> usize {
+        let mut ptr = node;
+        while let Some(c) = self.tree[ptr].last_child() {
+            ptr = c;
+        }
+        self.lines.inds[&ptr]
+    }
+
+    /// The indents of the lines for the children of `node`.
+    fn child_indents(&self, node: NodeId) -> Vec<PrefixPiece> {
+        let mut indents = self.lines.lines[self.lines.inds[&node]].prefix.clone();
+        if let Some(last) = indents.pop() {
+            indents.push(if last == PrefixPiece::EndBranch {
+                PrefixPiece::BlankIndent
+            } else {
+                PrefixPiece::BarIndent
+            });
+        }
+        indents
+    }
+
+    /// Stop the scan, if it is still running, leaving the tree partial.
+    pub fn cancel_scan(&mut self) {
+        if let Some(mut scan) = self.scan.take() {
+            scan.cancel();
+            self.cancelled = true;
+        }
+    }
+
+    /// Start watching the FS for entries being created, removed or renamed
+    /// beneath the root, to apply to the tree each time `poll_watch` is called.
+    ///
+    /// If the tree is still being built, directories are only watched once the
+    /// scan finishes. Unread directories are watched once they are read.
+    pub fn watch(&mut self) -> io::Result<()> {
+        self.watcher = Some(Watcher::new()?);
+        if self.scan.is_none() {
+            let root = self.root;
+            self.watch_dirs(root);
+        }
+        Ok(())
+    }
+
+    /// Whether the FS is being watched for changes.
+    pub fn is_watching(&self) -> bool {
+        self.watcher.is_some()
+    }
+
+    /// Watch every directory that has been read at or beneath `node`, if the
+    /// FS is being watched.
+    ///
+    /// Stops at the first directory that can't be watched, most likely because
+    /// the limit on watches was reached, recording it in `errors`.
+    fn watch_dirs(&mut self, node: NodeId) {
+        if self.watcher.is_none() {
+            return;
+        }
+        let dirs = node.descendants(&self.tree)
+            .filter(|&n| self.is_dir(n) && !self.unread.contains(&n))
+            .collect::<Vec<NodeId>>();
+
+        let watcher = self.watcher.as_mut().unwrap();
+        for n in dirs {
+            let data = &self.tree[n].data;
+            if let Err(err) = watcher.watch(data.de.path()) {
+                let error = ItreeError::Io {
+                    path: Some(data.de.path().to_owned()),
+                    err: io::Error::new(
+                        err.kind(),
+                        format!("Could not watch for changes: {}", err),
+                    ),
+                };
+                self.errors.push(EntryError { node: n, error });
+                return;
+            }
+        }
+    }
+
+    /// Apply the changes to the FS seen since the last poll to the tree.
+    /// Fold state, the focus, and which child to focus in each directory are
+    /// kept, following entries that were renamed. If changes came too fast to
+    /// follow, the whole tree is read again.
+    ///
+    /// Returns whether the tree changed.
+    pub fn poll_watch(&mut self) -> bool {
+        let changes = match self.watcher.as_mut().map(|w| w.changes()) {
+            Some(Ok(changes)) => changes,
+            Some(Err(err)) => {
+                let error = ItreeError::Io { path: None, err };
+                self.errors.push(EntryError {
+                    node: self.root,
+                    error,
+                });
+                self.watcher = None;
+                return false;
+            }
+            None => return false,
+        };
+
+        // If changes were lost, it's no use applying the rest.
+        if changes.contains(&Change::Overflow) {
+            self.rebuild();
+            return true;
+        }
+
+        let mut state = self.line_state();
+        let mut changed = false;
+        for change in changes {
+            changed |= match change {
+                Change::Created(path) => self.apply_created(&path, &mut state).is_some(),
+                Change::Removed(path) => self.apply_removed(&path),
+                Change::Moved { from, to } => self.apply_moved(&from, &to, &mut state),
+                Change::Overflow => unreachable!("Lost changes are handled above"),
+            };
+        }
+        if !changed {
+            return false;
+        }
+
+        // The focus may have followed an entry into a folded directory.
+        self.edited(state);
+        true
+    }
+
+    /// Add the entry created at `path` to the tree, unless it is already in
+    /// it, or its directory isn't, or hasn't been read.
+    ///
+    /// Returns the entry's node, if it was added.
+    fn apply_created(&mut self, path: &Path, state: &mut LineState) -> Option<NodeId> {
+        if self.node_at(path).is_some() {
+            return None;
+        }
+        let dir = match path.parent().and_then(|p| self.node_at(p)) {
+            Some(dir) if !self.unread.contains(&dir) => dir,
+            _ => return None,
+        };
+
+        let depth = dir.ancestors(&self.tree).count() - 1;
+        match load_entry(&self.options, &mut self.tree, dir, depth, path) {
+            Ok(added) => self.add_loaded(added, state),
+            Err(error) => {
+                self.errors.push(EntryError { node: dir, error });
+                return None;
+            }
+        }
+
+        let node = self.node_at(path)?;
+        self.watch_dirs(node);
+        self.changed.insert(node, Instant::now());
+        Some(node)
+    }
+
+    /// Remove the entry at `path` from the tree, along with everything beneath it.
+    ///
+    /// Returns whether it was in the tree.
+    fn apply_removed(&mut self, path: &Path) -> bool {
+        let node = match self.node_at(path) {
+            Some(n) if n != self.root => n,
+            _ => return false,
+        };
+
+        let parent = self.tree[node].parent().expect("The node should have a parent");
+        self.detach_subtree(node);
+        self.changed.insert(parent, Instant::now());
+        true
+    }
+
+    /// Move the entry at `from` to `to`, reading it again at its new path, and
+    /// keeping the fold state and focus of everything beneath it.
+    ///
+    /// Returns whether the tree changed.
+    fn apply_moved(&mut self, from: &Path, to: &Path, state: &mut LineState) -> bool {
+        let paths = match self.node_at(from).filter(|&n| n != self.root) {
+            Some(node) => self.path_state(node, state),
+            None => PathState::default(),
+        };
+
+        let removed = self.apply_removed(from);
+        // A move replaces whatever was at its destination.
+        let replaced = self.apply_removed(to);
+        if self.apply_created(to, state).is_none() {
+            return removed || replaced;
+        }
+
+        self.restore_path_state(to, paths, state);
+        true
+    }
+
+    /// Read the focused directory, or the directory containing the focused
+    /// file, again from the FS, replacing everything beneath it.
+    ///
+    /// Fold state and the focus are kept for the entries that are still there.
+    pub fn refresh_focused(&mut self) {
+        let f = self.focused;
+        let dir = if self.is_dir(f) || self.tree[f].data.ft == FileType::RestrictedDir {
+            f
+        } else {
+            self.tree[f].parent().unwrap_or(self.root)
+        };
+
+        self.refresh(dir);
+    }
+
+    /// Read the whole tree again from the FS, stopping the scan if it is still
+    /// running.
+    ///
+    /// Fold state and the focus are kept for the entries that are still there.
+    pub fn rebuild(&mut self) {
+        self.cancel_scan();
+        self.cancelled = false;
+
+        let root = self.root;
+        self.refresh(root);
+    }
+
+    /// Read `dir` again from the FS, replacing everything beneath it, and
+    /// keeping fold state and the focus by path.
+    fn refresh(&mut self, dir: NodeId) {
+        let mut state = self.line_state();
+        let paths = self.path_state(dir, &state);
+        let path = self.tree[dir].data.de.path().to_owned();
+
+        let children = dir.children(&self.tree).collect::<Vec<NodeId>>();
+        for child in children {
+            self.detach_subtree(child);
+        }
+        if dir == self.root {
+            self.n_files = 0;
+            self.n_dirs = 0;
+        }
+        self.errors.retain(|e| e.node != dir);
+        self.unread.remove(&dir);
+        if self.tree[dir].data.ft == FileType::RestrictedDir {
+            self.tree[dir].data.ft = FileType::Dir;
+        }
+
+        let depth = dir.ancestors(&self.tree).count() - 1;
+        match load_dir(&self.options, &mut self.tree, dir, depth) {
+            Ok(added) => {
+                self.add_loaded(added, &mut state);
+                self.watch_dirs(dir);
+            }
+            Err(ItreeError::NotFound(_)) if dir != self.root => self.detach_subtree(dir),
+            Err(error) => self.errors.push(EntryError { node: dir, error }),
+        }
+
+        self.restore_path_state(&path, paths, &mut state);
+        self.loaded_more = true;
+        aggregate_sizes(&mut self.tree, self.root);
+        self.redraw(state);
+    }
+
+    /// Account for entries just added to the tree, folding the directories
+    /// among them that were left unread.
+    fn add_loaded(&mut self, added: Added, state: &mut LineState) {
+        self.n_files += added.n_files;
+        self.n_dirs += added.n_dirs;
+        self.errors.extend(added.errors);
+        state.folded.extend(added.unread.iter().cloned());
+        self.unread.extend(added.unread);
+    }
+
+    /// Take `node` and everything beneath it out of the tree, forgetting
+    /// anything kept about them, and moving the focus off them if needed.
+    ///
+    /// The lines must be redrawn afterward.
+    fn detach_subtree(&mut self, node: NodeId) {
+        let parent = self.tree[node].parent().expect("The node should have a parent");
+        let removed = node.descendants(&self.tree).collect::<HashSet<NodeId>>();
+
+        for &n in removed.iter() {
+            if counts_as_dir(&self.tree[n].data) {
+                self.n_dirs = self.n_dirs.saturating_sub(1);
+            } else {
+                self.n_files = self.n_files.saturating_sub(1);
+            }
+            self.unread.remove(&n);
+            self.unopened.remove(&n);
+            self.changed.remove(&n);
+        }
+        self.errors.retain(|e| !removed.contains(&e.node));
+        self.focused_child
+            .retain(|p, c| !removed.contains(p) && !removed.contains(c));
+
+        if removed.contains(&self.focused) {
+            self.focused = self.tree[node]
+                .next_sibling()
+                .or(self.tree[node].previous_sibling())
+                .unwrap_or(parent);
+        }
+
+        node.detach(&mut self.tree);
+    }
+
+    /// The focus, fold state and focused children at or beneath `node`, by
+    /// path relative to it.
+    fn path_state(&self, node: NodeId, state: &LineState) -> PathState {
+        let base = self.tree[node].data.de.path();
+        let rel = |n: NodeId| {
+            self.tree[n]
+                .data
+                .de
+                .path()
+                .strip_prefix(base)
+                .map(|p| p.to_owned())
+                .ok()
+        };
+
+        let mut paths = PathState::default();
+        for n in node.descendants(&self.tree) {
+            if n == self.focused {
+                paths.focused = rel(n);
+            }
+            if state.folded.contains(&n) {
+                paths.folded.extend(rel(n));
+            }
+            if let Some(&c) = self.focused_child.get(&n) {
+                paths.focused_child.extend(rel(n).into_iter().zip(rel(c)));
+            }
+        }
+
+        paths
+    }
+
+    /// Restore state saved by `path_state` to the entries now beneath `base`.
+    fn restore_path_state(&mut self, base: &Path, paths: PathState, state: &mut LineState) {
+        for p in paths.folded {
+            if let Some(n) = self.node_at(&base.join(p)) {
+                state.folded.insert(n);
+            }
+        }
+        for (p, c) in paths.focused_child {
+            if let (Some(p), Some(c)) = (self.node_at(&base.join(p)), self.node_at(&base.join(c))) {
+                self.focused_child.insert(p, c);
+            }
+        }
+        if let Some(n) = paths.focused.and_then(|f| self.node_at(&base.join(f))) {
+            self.focused = n;
+        }
+    }
+
+    /// Add `entry` to the tree as a child of `parent`, which should be a
+    /// directory, among its children in sort order.
+    ///
+    /// Only the tree changes, not the FS. Returns the new node.
+    pub fn insert_child(&mut self, parent: NodeId, entry: FsEntry) -> NodeId {
+        let state = self.line_state();
+
+        if counts_as_dir(&entry) {
+            self.n_dirs += 1;
+        } else {
+            self.n_files += 1;
+        }
+        let node = self.tree.new_node(entry);
+        self.place_sorted(parent, node);
+
+        self.edited(state);
+        node
+    }
+
+    /// Remove `node` and everything beneath it from the tree. If the focus was
+    /// among them, it moves to a sibling, or failing that, the parent.
+    ///
+    /// Only the tree changes, not the FS. Panics if `node` is the root.
+    pub fn remove_subtree(&mut self, node: NodeId) {
+        assert!(node != self.root, "Can't remove the root");
+        let state = self.line_state();
+        self.detach_subtree(node);
+        self.edited(state);
+    }
+
+    /// Move `node` and everything beneath it to be a child of `parent`, among
+    /// its children in sort order.
+    ///
+    /// Only the tree changes, not the FS, so entries keep the paths they were
+    /// read at. Panics if `node` is the root, or `parent` is beneath `node`.
+    pub fn move_subtree(&mut self, node: NodeId, parent: NodeId) {
+        assert!(node != self.root, "Can't move the root");
+        assert!(
+            !parent.ancestors(&self.tree).any(|a| a == node),
+            "Can't move a node beneath itself"
+        );
+
+        let state = self.line_state();
+        if let Some(old) = self.tree[node].parent() {
+            if self.focused_child.get(&old) == Some(&node) {
+                self.focused_child.remove(&old);
+            }
+        }
+        self.place_sorted(parent, node);
+        self.edited(state);
+    }
+
+    /// Change the name `node` is shown with, moving it to keep its siblings
+    /// in sort order.
+    ///
+    /// Only the tree changes, not the FS, so the entry keeps the path it was
+    /// read at.
+    pub fn rename(&mut self, node: NodeId, name: OsString) {
+        let state = self.line_state();
+        self.tree[node].data.name = name;
+        if let Some(parent) = self.tree[node].parent() {
+            self.place_sorted(parent, node);
+        }
+        self.edited(state);
+    }
+
+    /// Put `node` among the children of `parent`, before the first that
+    /// sorts after it.
+    fn place_sorted(&mut self, parent: NodeId, node: NodeId) {
+        node.detach(&mut self.tree);
+
+        let sort = self.options.sort;
+        let entry = &self.tree[node].data;
+        match parent.children(&self.tree).find(|&c| {
+            compare_entries(sort, &self.tree[c].data, entry) == cmp::Ordering::Greater
+        }) {
+            Some(next) => next.insert_before(node, &mut self.tree),
+            None => parent.append(node, &mut self.tree),
+        }
+    }
+
+    /// How the entries in each directory are ordered.
+    pub fn sort_order(&self) -> SortOrder {
+        self.options.sort
     }
 
-    fn focused_line_mut(&mut self) -> &mut TreeLine {
+    /// Re-order the entries in every directory in `sort` order, without
+    /// reading the FS again. Entries read from now on are ordered the same way.
+    pub fn sort(&mut self, sort: SortOrder) {
+        let state = self.line_state();
+        self.options.sort = sort;
+        if let Some(scan) = self.scan.as_mut() {
+            scan.set_sort(sort);
+        }
+
+        let dirs = self.root
+            .descendants(&self.tree)
+            .filter(|&n| self.tree[n].first_child().is_some())
+            .collect::<Vec<NodeId>>();
+        for dir in dirs {
+            sort_children(&mut self.tree, dir, sort);
+        }
+
+        self.edited(state);
+    }
+
+    /// Bring sizes and lines up to date after an edit, restoring the fold and
+    /// filter state in `state`, which was saved before it.
+    ///
+    /// If the focus was moved into a folded directory, it is unfolded.
+    fn edited(&mut self, state: LineState) {
+        self.loaded_more = true;
+        aggregate_sizes(&mut self.tree, self.root);
+        self.redraw(state);
+
         let f = self.focused;
-        self.line_for_node_mut(f)
+        self.focus_node(f);
     }
 
-    fn focused_line_ind(&self) -> usize {
-        self.lines.inds[&self.focused]
+    /// Stop highlighting entries changed longer than `age` ago.
+    ///
+    /// Returns whether any highlights were removed.
+    pub fn expire_changes(&mut self, age: Duration) -> bool {
+        let n = self.changed.len();
+        self.changed.retain(|_, &mut at| at.elapsed() < age);
+        self.changed.len() != n
+    }
+
+    /// Whether `node` is a directory that could be read, or a link to one that
+    /// is followed.
+    fn is_dir(&self, node: NodeId) -> bool {
+        match self.tree[node].data.ft {
+            FileType::Dir => true,
+            FileType::LinkTo {
+                kind: LinkKind::Dir,
+                ..
+            } => self.options.follow_links,
+            _ => false,
+        }
+    }
+
+    /// The node for the entry at `path`, if it is in the tree.
+    fn node_at(&self, path: &Path) -> Option<NodeId> {
+        let rel = path.strip_prefix(self.tree[self.root].data.de.path()).ok()?;
+
+        let mut node = self.root;
+        for comp in rel.components() {
+            node = node.children(&self.tree)
+                .find(|&c| self.tree[c].data.de.file_name() == comp.as_os_str())?;
+        }
+
+        Some(node)
+    }
+
+    /// The entry currently under the cursor.
+    pub fn focused(&self) -> &FsEntry {
+        &self.tree[self.focused].data
+    }
+
+    /// The absolute path of the entry currently under the cursor.
+    pub fn focused_path(&self) -> io::Result<PathBuf> {
+        absolute_path(self.tree[self.focused].data.de.path())
+    }
+
+    fn line_for_node_mut(&mut self, node: NodeId) -> &mut TreeLine {
+        &mut self.lines.lines[self.lines.inds[&node]]
+    }
+
+    fn is_folded(&self, node: NodeId) -> bool {
+        self.lines.folded.contains(&node)
     }
 
     pub fn focus_up(&mut self) {
@@ -129,15 +951,24 @@ impl Tree {
                 }
             }
         };
+        self.load_focused();
     }
 
     pub fn focus_down(&mut self) {
+        let f = self.focused;
+        if self.unread.contains(&f) || self.unopened.contains(&f) {
+            self.unfold(f);
+        }
+
         self.focused = match self.focused_child.get(&self.focused) {
-            Some(&c) => c,
-            None => match self.tree[self.focused].first_child() {
+            Some(&c) if self.is_kept(c) && !self.is_folded(f) => c,
+            _ => match self.focused
+                .children(&self.tree)
+                .find(|&c| self.is_kept(c))
+            {
                 None => self.focused,
                 Some(ps) => {
-                    if self.lines.folded.contains(&self.focused_line_ind()) {
+                    if self.is_folded(self.focused) {
                         self.focused
                     } else {
                         ps
@@ -148,64 +979,179 @@ impl Tree {
     }
 
     pub fn focus_left(&mut self) {
-        self.focused = match self.tree[self.focused].previous_sibling() {
+        self.focused = match self.focused
+            .preceding_siblings(&self.tree)
+            .skip(1)
+            .find(|&s| self.is_kept(s))
+        {
             None => self.focused,
             Some(ps) => ps,
         };
+        self.load_focused();
     }
 
     pub fn focus_right(&mut self) {
-        self.focused = match self.tree[self.focused].next_sibling() {
+        self.focused = match self.focused
+            .following_siblings(&self.tree)
+            .skip(1)
+            .find(|&s| self.is_kept(s))
+        {
             None => self.focused,
             Some(ps) => ps,
         };
+        self.load_focused();
     }
 
     pub fn toggle_focus_fold(&mut self) {
-        if self.tree[self.focused].data.ft == FileType::Dir {
-            if self.lines.folded.contains(&self.focused_line_ind()) {
-                self.unfold_focus();
+        let f = self.focused;
+        if self.unread.contains(&f) {
+            self.unfold(f);
+        } else if self.tree[f].data.ft == FileType::Dir {
+            if self.is_folded(f) {
+                self.unfold(f);
             } else {
-                self.fold_focus();
+                self.fold(f);
             }
         }
     }
 
-    fn unfold_focus(&mut self) {
-        let f_ind = self.focused_line_ind();
+    /// Move the focus to `node`, unfolding any folded directories above it.
+    pub fn focus_node(&mut self, node: NodeId) {
+        if node == self.root {
+            return;
+        }
 
-        let mut ptr = self.focused;
-        while let Some(c) = self.tree[ptr].last_child() {
-            ptr = c;
+        let ancestors = node.ancestors(&self.tree).skip(1).collect::<Vec<NodeId>>();
+        for a in ancestors.into_iter().rev() {
+            if self.is_folded(a) {
+                self.unfold(a);
+            }
         }
 
-        // If the focus's next is in the tree,
-        // set its previous to the new previous
-        let n_ind = self.lines.lines[f_ind].next;
-        if n_ind < self.lines.count {
-            self.lines.lines[n_ind].prev = Some(self.lines.inds[&ptr]);
+        self.focused = node;
+        self.load_focused();
+    }
+
+    /// Read the focused directory's children if they have not been read yet,
+    /// leaving it folded, so that moving into it or unfolding it is instant.
+    fn load_focused(&mut self) {
+        let f = self.focused;
+        if self.load(f) {
+            self.unopened.insert(f);
+            self.relink();
         }
+    }
 
-        // Mark this line as unfolded
-        self.lines.folded.remove(&f_ind);
+    /// Find every node whose name matches `matcher`, in the order they appear in the tree.
+    ///
+    /// Nodes hidden inside folded directories are included, but nodes hidden
+    /// by the active filter are not.
+    pub fn find_matches(&self, matcher: &Matcher) -> Vec<NodeId> {
+        self.lines
+            .lines
+            .iter()
+            .skip(1)
+            .map(|l| l.node)
+            .filter(|&n| self.is_kept(n) && self.is_match(matcher, n))
+            .collect()
+    }
 
-        // Set the focus's next to the focus + 1
-        let fl = self.focused_line_mut();
-        fl.next = f_ind + 1;
+    /// Add the nodes among `nodes` that `find_matches` would find to `hits`,
+    /// keeping them in the order they appear in the tree.
+    pub(crate) fn add_matches(&self, matcher: &Matcher, nodes: &[NodeId], hits: &mut Vec<NodeId>) {
+        let found = hits.len();
+        hits.extend(
+            nodes
+                .iter()
+                .cloned()
+                .filter(|&n| self.is_kept(n) && self.is_match(matcher, n)),
+        );
+        if hits.len() > found {
+            hits.sort_by_key(|n| self.lines.inds[n]);
+        }
     }
 
-    fn fold_focus(&mut self) {
-        if !self.tree[self.focused]
-            .data
-            .de
-            .file_type()
-            .unwrap()
-            .is_dir()
-        {
-            return;
+    fn is_match(&self, matcher: &Matcher, node: NodeId) -> bool {
+        matcher.is_match(&self.display_name(node))
+    }
+
+    /// The name of a node as shown, and so as matched.
+    pub(crate) fn display_name(&self, node: NodeId) -> String {
+        self.display_os_str(&self.tree[node].data.name)
+    }
+
+    /// `s` escaped if the tree was built with `escape_names` set, or with any
+    /// invalid UTF-8 replaced otherwise.
+    pub(crate) fn display_os_str(&self, s: &OsStr) -> String {
+        if self.options.escape_names {
+            escape_name(s)
+        } else {
+            s.to_string_lossy().into_owned()
         }
+    }
+
+    /// Hide every node whose subtree has no name matching `matcher`, keeping the
+    /// ancestors of matches visible. `None` removes the filter.
+    ///
+    /// Returns the nodes that matched, in the order they appear in the tree.
+    pub fn filter(&mut self, matcher: Option<&Matcher>) -> Vec<NodeId> {
+        self.lines.kept = None;
+        self.filter = matcher.cloned();
+
+        let hits = match matcher {
+            Some(m) => {
+                let hits = self.find_matches(m);
 
-        let mut ptr = Some(self.focused);
+                let mut kept = HashSet::new();
+                kept.insert(0);
+                for &hit in hits.iter() {
+                    for a in hit.ancestors(&self.tree) {
+                        if !kept.insert(self.lines.inds[&a]) {
+                            break;
+                        }
+                    }
+                }
+
+                self.lines.kept = Some(kept);
+                hits
+            }
+            None => Vec::new(),
+        };
+
+        self.redraw_prefixes();
+        self.relink();
+
+        if !self.is_kept(self.focused) {
+            let f_ind = self.lines.inds[&self.focused];
+            let next_kept = {
+                let kept = self.lines.kept.as_ref().unwrap();
+                (f_ind..self.lines.count)
+                    .chain(1..f_ind)
+                    .find(|i| kept.contains(i))
+            };
+
+            match next_kept {
+                Some(i) => {
+                    let node = self.lines.lines[i].node;
+                    self.focus_node(node);
+                }
+                None => self.focused = self.root,
+            }
+        }
+
+        hits
+    }
+
+    pub(crate) fn is_kept(&self, node: NodeId) -> bool {
+        match &self.lines.kept {
+            Some(kept) => kept.contains(&self.lines.inds[&node]),
+            None => true,
+        }
+    }
+
+    /// The index of the first line after all of those beneath `node`.
+    fn line_after(&self, node: NodeId) -> usize {
+        let mut ptr = Some(node);
         while let Some(p) = ptr {
             if let Some(n) = self.tree[p].next_sibling() {
                 ptr = Some(n);
@@ -215,40 +1161,336 @@ impl Tree {
             }
         }
 
-        let new_next = match ptr {
+        match ptr {
             Some(nn) => self.lines.inds[&nn],
             None => self.lines.count,
-        };
+        }
+    }
+
+    /// The fold and filter state of the lines, by node.
+    fn line_state(&self) -> LineState {
+        LineState {
+            folded: self.lines.folded.clone(),
+            kept: self.lines.kept.as_ref().map(|kept| {
+                kept.iter()
+                    .map(|&i| self.lines.lines[i].node)
+                    .collect::<HashSet<NodeId>>()
+            }),
+        }
+    }
+
+    /// Draw every line from scratch after the tree changed shape, restoring
+    /// the fold and filter state in `state` for the nodes still in the tree.
+    ///
+    /// If the root is focused, the focus moves to its first child.
+    fn redraw(&mut self, state: LineState) {
+        self.lines = Tree::draw(&self.tree, self.root);
+        self.lines.folded = state
+            .folded
+            .into_iter()
+            .filter(|n| self.lines.inds.contains_key(n))
+            .collect();
+        if let Some(kept) = state.kept {
+            let kept = kept.iter()
+                .filter_map(|n| self.lines.inds.get(n).cloned())
+                .collect();
+            self.lines.kept = Some(kept);
+            self.redraw_prefixes();
+        }
+        self.relink();
+
+        if self.focused == self.root {
+            let root = self.root;
+            if let Some(c) = root.children(&self.tree).find(|&c| self.is_kept(c)) {
+                self.focused = c;
+            }
+        }
+    }
+
+    /// Recompute every line's `prev` and `next` from scratch, skipping
+    /// lines that are folded away or filtered out.
+    ///
+    /// The lines beneath each folded directory are linked too, as though
+    /// it were unfolded, so that `unfold` only has to link their ends.
+    fn relink(&mut self) {
+        let count = self.lines.count;
+        self.link_lines(0, count, None);
+
+        let folded = self.lines.folded.iter().cloned().collect::<Vec<NodeId>>();
+        for f in folded {
+            let f_ind = self.lines.inds[&f];
+            let end = self.line_after(f);
+            self.link_lines(f_ind + 1, end, Some(f_ind));
+        }
+    }
+
+    /// Link the lines from `start` up to `end` that aren't folded away or
+    /// filtered out, the first back to `prev` and the last on to `end`.
+    fn link_lines(&mut self, start: usize, end: usize, mut prev: Option<usize>) {
+        let mut i = start;
+        while i < end {
+            let node = self.lines.lines[i].node;
+            if !self.is_kept(node) {
+                i = self.line_after(node);
+                continue;
+            }
+
+            if let Some(p) = prev.filter(|&p| p >= start) {
+                self.lines.lines[p].next = i;
+            }
+            self.lines.lines[i].prev = prev;
+            prev = Some(i);
+
+            i = if self.lines.folded.contains(&node) {
+                self.line_after(node)
+            } else {
+                i + 1
+            };
+        }
 
-        // If the focus's new_next is in the tree,
-        // set its previous to the focus
+        if let Some(p) = prev.filter(|&p| p >= start) {
+            self.lines.lines[p].next = end;
+        }
+    }
+
+    /// Recompute every visible line's prefix, so that branches are drawn
+    /// as though filtered out lines did not exist.
+    fn redraw_prefixes(&mut self) {
+        let root = self.root;
+        self.redraw_prefixes_from(root, &mut vec![]);
+    }
+
+    fn redraw_prefixes_from(&mut self, node: NodeId, indents: &mut Vec<PrefixPiece>) {
+        let children = node.children(&self.tree)
+            .filter(|&c| self.is_kept(c))
+            .collect::<Vec<NodeId>>();
+
+        for (i, &child) in children.iter().enumerate() {
+            let last = i == children.len() - 1;
+
+            let mut prefix = indents.clone();
+            prefix.push(if last {
+                PrefixPiece::EndBranch
+            } else {
+                PrefixPiece::MidBranch
+            });
+            self.line_for_node_mut(child).prefix = prefix;
+
+            indents.push(if last {
+                PrefixPiece::BlankIndent
+            } else {
+                PrefixPiece::BarIndent
+            });
+            self.redraw_prefixes_from(child, indents);
+            indents.pop();
+        }
+    }
+
+    fn unfold(&mut self, node: NodeId) {
+        self.unopened.remove(&node);
+        let loaded = self.load(node);
+        let f_ind = self.lines.inds[&node];
+
+        if loaded || self.lines.kept.is_some() {
+            self.lines.folded.remove(&node);
+            self.relink();
+            return;
+        }
+
+        // Find the last line that will be visible beneath the node,
+        // without descending into any directories that are still folded.
+        let mut ptr = node;
+        while let Some(c) = self.tree[ptr].last_child() {
+            ptr = c;
+            if self.is_folded(ptr) {
+                break;
+            }
+        }
+
+        // If the node's next is in the tree,
+        // set its previous to the new previous
+        let n_ind = self.lines.lines[f_ind].next;
+        if n_ind < self.lines.count {
+            self.lines.lines[n_ind].prev = Some(self.lines.inds[&ptr]);
+        }
+
+        // Mark this line as unfolded
+        self.lines.folded.remove(&node);
+
+        // Set the node's next to the node + 1
+        let fl = self.line_for_node_mut(node);
+        fl.next = f_ind + 1;
+    }
+
+    fn fold(&mut self, node: NodeId) {
+        if !self.tree[node].data.de.file_type().unwrap().is_dir() {
+            return;
+        }
+        self.unopened.remove(&node);
+
+        let f_ind = self.lines.inds[&node];
+        if self.lines.kept.is_some() {
+            self.lines.folded.insert(node);
+            self.relink();
+            return;
+        }
+
+        let new_next = self.line_after(node);
+
+        // If the node's new_next is in the tree,
+        // set its previous to the node
         if new_next < self.lines.count {
-            self.lines.lines[new_next].prev = Some(self.lines.inds[&self.focused]);
+            self.lines.lines[new_next].prev = Some(f_ind);
         }
 
         // Mark this line folded
-        let f_ind = self.focused_line_ind();
-        self.lines.folded.insert(f_ind);
+        self.lines.folded.insert(node);
 
-        // Set the focus's next to the new_next
-        let fl = self.focused_line_mut();
+        // Set the node's next to the new_next
+        let fl = self.line_for_node_mut(node);
         fl.next = new_next;
     }
 
+    /// Read the children of `node`, if they have not been read yet, and add
+    /// them to the tree beneath it. Unread directories among them are folded.
+    ///
+    /// Returns whether anything was read. If so, the lines will need relinking.
+    fn load(&mut self, node: NodeId) -> bool {
+        if !self.unread.remove(&node) {
+            return false;
+        }
+
+        let depth = node.ancestors(&self.tree).count() - 1;
+        let added = match load_dir(&self.options, &mut self.tree, node, depth) {
+            Ok(added) => added,
+            Err(error) => {
+                // It won't be read again, so show it as the directory it can't be.
+                self.tree[node].data.ft = FileType::RestrictedDir;
+                self.errors.push(EntryError { node, error });
+                return false;
+            }
+        };
+        self.n_files += added.n_files;
+        self.n_dirs += added.n_dirs;
+        self.errors.extend(added.errors);
+        self.loaded_more = true;
+
+        let old_size = self.tree[node].data.total_size;
+        let added_size = aggregate_sizes(&mut self.tree, node) - old_size;
+        let ancestors = node.ancestors(&self.tree).skip(1).collect::<Vec<NodeId>>();
+        for a in ancestors {
+            self.tree[a].data.total_size += added_size;
+        }
+
+        let mut indents = self.child_indents(node);
+        let mut new_lines = TreeLines::new();
+        Tree::draw_from(&mut new_lines, &self.tree, node, &mut indents);
+        self.lines.insert(self.lines.inds[&node] + 1, new_lines.lines);
+        self.keep_matches_beneath(node);
+
+        for dir in added.unread {
+            self.lines.folded.insert(dir);
+            self.unread.insert(dir);
+        }
+        self.watch_dirs(node);
+
+        true
+    }
+
+    /// Keep the lines beneath `node` matching the active filter, and their
+    /// ancestors, as `filter` would have if they had been read when it ran.
+    fn keep_matches_beneath(&mut self, node: NodeId) {
+        let hits = match (&self.filter, &self.lines.kept) {
+            (Some(m), Some(_)) => node.descendants(&self.tree)
+                .skip(1)
+                .filter(|&n| self.is_match(m, n))
+                .collect::<Vec<NodeId>>(),
+            _ => return,
+        };
+
+        for hit in hits {
+            self.keep_with_ancestors(hit);
+        }
+        self.redraw_prefixes();
+    }
+
+    /// Keep the line for `node` through the active filter, along with those of
+    /// its ancestors.
+    fn keep_with_ancestors(&mut self, node: NodeId) {
+        if let Some(kept) = self.lines.kept.as_mut() {
+            for a in node.ancestors(&self.tree) {
+                if !kept.insert(self.lines.inds[&a]) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Errors reading entries beneath the root, in the order they were found,
+    /// each with the node it concerns.
+    ///
+    /// Directories that could not be read are kept in the tree, but marked
+    /// `FileType::RestrictedDir`, and their errors are included here too.
+    pub fn errors(&self) -> &[EntryError] {
+        &self.errors
+    }
+
+    /// Whether some directories have not been read yet, so the tree is incomplete.
+    pub fn is_partial(&self) -> bool {
+        !self.unread.is_empty() || self.scan.is_some() || self.cancelled
+    }
+
+    /// The total size of the tree, counting every entry in it.
+    pub fn total_size(&self) -> u64 {
+        self.tree[self.root].data.total_size
+    }
+
+    /// The number of files and directories in the tree, not counting the root.
+    ///
+    /// If a filter is active, only the files and directories left visible are counted.
+    pub(crate) fn counts(&self) -> (usize, usize) {
+        match &self.lines.kept {
+            Some(kept) => kept.iter()
+                .filter(|&&i| i != 0)
+                .fold((0, 0), |(files, dirs), &i| {
+                    let node = self.lines.lines[i].node;
+                    if counts_as_dir(&self.tree[node].data) {
+                        (files, dirs + 1)
+                    } else {
+                        (files + 1, dirs)
+                    }
+                }),
+            None => (self.n_files, self.n_dirs),
+        }
+    }
+
     pub fn summary(&self) -> String {
+        let (n_files, n_dirs) = self.counts();
         format!(
-            "{} {}, {} {}",
-            self.n_dirs,
-            if self.n_dirs == 1 {
+            "{} {}, {} {}{}{}",
+            n_dirs,
+            if n_dirs == 1 {
                 "directory"
             } else {
                 "directories"
             },
-            self.n_files,
-            if self.n_files == 1 { "file" } else { "files" }
+            n_files,
+            if n_files == 1 { "file" } else { "files" },
+            if self.errors.is_empty() {
+                String::new()
+            } else {
+                format!(", {}", self.errors_summary())
+            },
+            if self.is_partial() { " (partial)" } else { "" }
         )
     }
 
+    /// The number of errors reading the tree, e.g. "3 errors".
+    pub fn errors_summary(&self) -> String {
+        let n = self.errors.len();
+        format!("{} {}", n, if n == 1 { "error" } else { "errors" })
+    }
+
     fn draw(tree: &Arena<FsEntry>, root: NodeId) -> TreeLines {
         let mut tree_lines = TreeLines::new();
 
@@ -268,25 +1510,312 @@ impl Tree {
         indents: &mut Vec<PrefixPiece>,
     ) {
         for child in root.children(&tree) {
-            let last = Some(child) == tree[root].last_child();
+            Tree::draw_node(tree_lines, tree, child, indents);
+        }
+    }
 
-            let mut prefix = indents.clone();
-            prefix.push(if last {
-                PrefixPiece::EndBranch
-            } else {
-                PrefixPiece::MidBranch
-            });
+    /// Draw the line for `node`, with `indents` before its branch, and the
+    /// lines beneath it.
+    fn draw_node(
+        tree_lines: &mut TreeLines,
+        tree: &Arena<FsEntry>,
+        node: NodeId,
+        indents: &mut Vec<PrefixPiece>,
+    ) {
+        let last = tree[node].next_sibling().is_none();
 
-            tree_lines.add(child, prefix);
+        let mut prefix = indents.clone();
+        prefix.push(if last {
+            PrefixPiece::EndBranch
+        } else {
+            PrefixPiece::MidBranch
+        });
 
-            indents.push(if last {
-                PrefixPiece::BlankIndent
+        tree_lines.add(node, prefix);
+
+        indents.push(if last {
+            PrefixPiece::BlankIndent
+        } else {
+            PrefixPiece::BarIndent
+        });
+        Tree::draw_from(tree_lines, tree, node, indents);
+        indents.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use fs::read_entry;
+    use quickcheck::quickcheck;
+
+    fn test_dir(dir: &str) -> PathBuf {
+        PathBuf::new().join("resources/test").join(dir)
+    }
+
+    /// Check that the lines are those drawn from the tree, that each is
+    /// indexed by its node, and that following `next` from the root visits
+    /// every line not inside a folded directory, linked back by `prev`.
+    fn check_lines(t: &Tree) {
+        let fresh = Tree::draw(&t.tree, t.root);
+        let drawn = |lines: &TreeLines| {
+            lines.lines
+                .iter()
+                .map(|l| (l.node, l.prefix.clone()))
+                .collect::<Vec<(NodeId, Vec<PrefixPiece>)>>()
+        };
+        assert_eq!(drawn(&fresh), drawn(&t.lines));
+        assert_eq!(t.lines.lines.len(), t.lines.count);
+        assert_eq!(fresh.inds, t.lines.inds);
+
+        for &node in t.lines.folded.iter() {
+            assert!(t.lines.inds.contains_key(&node));
+            assert!(t.tree[node].data.de.file_type().unwrap().is_dir());
+        }
+
+        let mut visible = Vec::new();
+        let mut i = 0;
+        while i < t.lines.count {
+            visible.push(i);
+            i = if t.lines.folded.contains(&t.lines.lines[i].node) {
+                t.line_after(t.lines.lines[i].node)
             } else {
-                PrefixPiece::BarIndent
-            });
-            Tree::draw_from(tree_lines, tree, child, indents);
+                i + 1
+            };
         }
 
-        indents.pop();
+        let mut linked = vec![0];
+        let mut prev = 0;
+        while t.lines.lines[prev].next < t.lines.count {
+            let next = t.lines.lines[prev].next;
+            assert_eq!(Some(prev), t.lines.lines[next].prev);
+            linked.push(next);
+            prev = next;
+        }
+        assert_eq!(visible, linked);
+
+        let f_ind = t.lines.inds[&t.focused];
+        assert!(t.focused == t.root || visible.contains(&f_ind));
+    }
+
+    /// Apply an edit to `t`, chosen by `op`, to nodes and entries chosen by
+    /// `a` and `b`.
+    fn edit(t: &mut Tree, op: u8, a: u8, b: u8) {
+        let nodes = t.root
+            .descendants(&t.tree)
+            .skip(1)
+            .collect::<Vec<NodeId>>();
+        let dirs = t.root
+            .descendants(&t.tree)
+            .filter(|&n| t.tree[n].data.de.file_type().unwrap().is_dir())
+            .collect::<Vec<NodeId>>();
+        let pick = |ns: &[NodeId], i: u8| ns[i as usize % ns.len()];
+
+        match op % 6 {
+            0 => {
+                let entries = ["simple/myfile", "one_dir", "link/dest"];
+                let entry = read_entry(&test_dir(entries[b as usize % entries.len()])).unwrap();
+                t.insert_child(pick(&dirs, a), entry);
+            }
+            1 if !nodes.is_empty() => t.remove_subtree(pick(&nodes, a)),
+            2 if !nodes.is_empty() => {
+                let (node, parent) = (pick(&nodes, a), pick(&dirs, b));
+                if !parent.ancestors(&t.tree).any(|p| p == node) {
+                    t.move_subtree(node, parent);
+                }
+            }
+            3 if !nodes.is_empty() => {
+                let names = ["a", "myfile", "one_dir", "zz", "Z"];
+                t.rename(pick(&nodes, a), names[b as usize % names.len()].into());
+            }
+            4 => {
+                t.focus_node(pick(&dirs, a));
+                t.toggle_focus_fold();
+            }
+            5 => match b % 4 {
+                0 => t.focus_up(),
+                1 => t.focus_down(),
+                2 => t.focus_left(),
+                _ => t.focus_right(),
+            },
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_edits() {
+        let mut t = Tree::new_from_dir(&test_dir("")).unwrap();
+        let simple = t.node_at(&test_dir("simple")).unwrap();
+        let one_dir = t.node_at(&test_dir("one_dir")).unwrap();
+        let (n_files, n_dirs) = (t.n_files, t.n_dirs);
+
+        let node = t.insert_child(simple, read_entry(&test_dir("one_dir/mydir")).unwrap());
+        assert_eq!(
+            vec!["mydir", "myfile", "myotherfile"],
+            simple
+                .children(&t.tree)
+                .map(|c| t.tree[c].data.name.to_str().unwrap())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!((n_files, n_dirs + 1), (t.n_files, t.n_dirs));
+        check_lines(&t);
+
+        t.rename(node, "zz".into());
+        assert_eq!(Some(node), t.tree[simple].last_child());
+        check_lines(&t);
+
+        t.focus_node(node);
+        t.move_subtree(simple, one_dir);
+        assert_eq!(Some(one_dir), t.tree[simple].parent());
+        assert_eq!(node, t.focused);
+        check_lines(&t);
+
+        // The focus moves to the nearest sibling.
+        t.remove_subtree(simple);
+        assert_eq!((n_files - 2, n_dirs - 1), (t.n_files, t.n_dirs));
+        assert_eq!("myotherfile", t.focused().name);
+        check_lines(&t);
+    }
+
+    #[test]
+    fn test_link_counts() {
+        use std::fs::create_dir_all;
+        use std::os::unix::fs::symlink;
+        use util::TempDir;
+
+        let dir = TempDir::new("link-counts");
+        create_dir_all(dir.join("a")).unwrap();
+        symlink("..", dir.join("a/up")).unwrap();
+        symlink("e", dir.join("e")).unwrap();
+        for name in ["b", "c", "d"].iter() {
+            symlink("nowhere", dir.join(name)).unwrap();
+        }
+
+        let mut opts = FsOptions::new(&dir);
+        opts.follow_links(true);
+        let mut t = Tree::new_with_options(opts).unwrap();
+        assert_eq!((3, 3), (t.n_files, t.n_dirs));
+
+        // Looping links are counted as directories, so they are uncounted as ones.
+        for path in ["a/up", "e"].iter() {
+            let node = t.node_at(&dir.join(path)).unwrap();
+            t.remove_subtree(node);
+        }
+        assert_eq!((3, 1), (t.n_files, t.n_dirs));
+        let b = t.node_at(&dir.join("b")).unwrap();
+        t.remove_subtree(b);
+        assert_eq!((2, 1), (t.n_files, t.n_dirs));
+    }
+
+    #[test]
+    fn test_folds_follow_nodes() {
+        use search::{MatchKind, Matcher};
+
+        let mut t = Tree::new_from_dir(&test_dir("")).unwrap();
+        let one_dir = t.node_at(&test_dir("one_dir")).unwrap();
+        let simple = t.node_at(&test_dir("simple")).unwrap();
+        for &dir in [one_dir, simple].iter() {
+            t.focus_node(dir);
+            t.toggle_focus_fold();
+        }
+        let folded = t.lines.folded.clone();
+
+        // Lines move, but the same directories stay folded.
+        let root = t.root;
+        t.insert_child(root, read_entry(&test_dir("one_dir/mydir")).unwrap());
+        t.rename(one_dir, "zz".into());
+        assert_eq!(folded, t.lines.folded);
+        check_lines(&t);
+
+        t.filter(Some(&Matcher::new("myfile", MatchKind::Substring).unwrap()));
+        assert_eq!(folded, t.lines.folded);
+        t.filter(None);
+        assert_eq!(folded, t.lines.folded);
+        check_lines(&t);
+    }
+
+    #[test]
+    fn test_random_edits() {
+        fn prop(edits: Vec<(u8, u8, u8)>) -> bool {
+            let mut t = Tree::new_from_dir(&test_dir("")).unwrap();
+            check_lines(&t);
+
+            for (op, a, b) in edits {
+                edit(&mut t, op, a, b);
+                check_lines(&t);
+            }
+
+            // Folding and unfolding everything still leaves the lines consistent.
+            let dirs = t.root.descendants(&t.tree).skip(1).collect::<Vec<NodeId>>();
+            for &d in dirs.iter().chain(dirs.iter().rev()) {
+                t.focus_node(d);
+                t.toggle_focus_fold();
+                check_lines(&t);
+            }
+
+            true
+        }
+
+        quickcheck(prop as fn(Vec<(u8, u8, u8)>) -> bool);
+    }
+
+    #[test]
+    fn test_random_scan() {
+        use fs::sort_children;
+        use search::{MatchKind, Matcher};
+
+        // Take the entries chosen by `picks` out of the tree, fold the
+        // directories chosen by `folds`, then put them back as a scan would.
+        fn prop(picks: Vec<u8>, folds: Vec<u8>, filter: bool) -> bool {
+            let mut t = Tree::new_from_dir(&test_dir("")).unwrap();
+            let root = t.root;
+            let nodes = root.descendants(&t.tree).skip(1).collect::<Vec<NodeId>>();
+
+            let mut taken = Vec::new();
+            for i in picks {
+                let node = nodes[i as usize % nodes.len()];
+                if node.ancestors(&t.tree).any(|a| a == root) {
+                    taken.push((node, t.tree[node].parent().unwrap()));
+                    node.detach(&mut t.tree);
+                }
+            }
+            t.focused = root;
+            let state = t.line_state();
+            t.redraw(state);
+
+            let dirs = root.descendants(&t.tree)
+                .skip(1)
+                .filter(|&n| t.tree[n].data.de.file_type().unwrap().is_dir())
+                .collect::<Vec<NodeId>>();
+            for i in folds.into_iter().filter(|_| !dirs.is_empty()) {
+                t.focus_node(dirs[i as usize % dirs.len()]);
+                t.toggle_focus_fold();
+            }
+            let matcher = Matcher::new("my", MatchKind::Substring).unwrap();
+            if filter {
+                t.filter(Some(&matcher));
+            }
+
+            let mut placed = Vec::new();
+            for &(node, parent) in taken.iter().rev() {
+                parent.append(node, &mut t.tree);
+                sort_children(&mut t.tree, parent, t.options.sort);
+                placed.extend(node.descendants(&t.tree));
+            }
+            t.draw_placed(&placed);
+
+            if filter {
+                let kept = t.lines.kept.clone();
+                t.filter(Some(&matcher));
+                assert_eq!(kept, t.lines.kept);
+                t.filter(None);
+            }
+            check_lines(&t);
+
+            true
+        }
+
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>, bool) -> bool);
     }
 }
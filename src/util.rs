@@ -29,3 +29,55 @@ impl<I: Iterator> PutBack<I> {
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) use self::temp::TempDir;
+
+#[cfg(test)]
+mod temp {
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::ops::Deref;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A directory of its own for a test to write to, removed with everything
+    /// in it when dropped.
+    pub struct TempDir(PathBuf);
+
+    impl TempDir {
+        /// Create an empty directory, with `name` in its name and unique to
+        /// this process and call.
+        pub fn new(name: &str) -> Self {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+            let dir = ::std::env::temp_dir().join(format!(
+                "itree-test-{}-{}-{}",
+                name,
+                ::std::process::id(),
+                COUNT.fetch_add(1, Ordering::SeqCst)
+            ));
+            create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for TempDir {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = remove_dir_all(&self.0);
+        }
+    }
+}
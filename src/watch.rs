@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use libc;
+
+/// A change to the entries of a watched directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Created(PathBuf),
+    Removed(PathBuf),
+    /// An entry was renamed, or moved from one watched directory to another.
+    Moved { from: PathBuf, to: PathBuf },
+    /// Changes came faster than they could be queued, and some were lost.
+    Overflow,
+}
+
+/// Watches directories, through inotify, for entries being created, removed
+/// or moved within them.
+///
+/// Only the directories given to `watch` are watched, not those beneath them.
+#[derive(Debug)]
+pub struct Watcher {
+    fd: libc::c_int,
+    /// The path of each watched directory, by watch descriptor.
+    dirs: HashMap<libc::c_int, PathBuf>,
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd,
+            dirs: HashMap::new(),
+        })
+    }
+
+    /// Start watching `dir`.
+    ///
+    /// Watching a directory that is already watched, e.g. under the new path
+    /// it was moved to, only updates its path.
+    pub fn watch(&mut self, dir: &Path) -> io::Result<()> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = CString::new(dir.as_os_str().as_bytes())?;
+        let mask = libc::IN_CREATE
+            | libc::IN_DELETE
+            | libc::IN_MOVED_FROM
+            | libc::IN_MOVED_TO
+            | libc::IN_ONLYDIR;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.dirs.insert(wd, dir.to_owned());
+        Ok(())
+    }
+
+    /// The changes seen since the last call, in the order they happened,
+    /// without waiting for any more.
+    ///
+    /// A move whose source or destination isn't watched is reported as a
+    /// removal or creation, respectively.
+    pub fn changes(&mut self) -> io::Result<Vec<Change>> {
+        use std::ffi::OsStr;
+        use std::mem;
+        use std::os::unix::ffi::OsStrExt;
+        use std::ptr;
+
+        let mut changes = Vec::new();
+        // Where the source of each move is in `changes`, by cookie.
+        let mut moves = HashMap::new();
+
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = unsafe {
+                libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+            };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::WouldBlock => break,
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(err),
+                }
+            }
+
+            let mut i = 0;
+            while i < n as usize {
+                let event = unsafe {
+                    ptr::read_unaligned(buf[i..].as_ptr() as *const libc::inotify_event)
+                };
+                let start = i + mem::size_of::<libc::inotify_event>();
+                i = start + event.len as usize;
+
+                if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    changes.push(Change::Overflow);
+                    continue;
+                }
+                if event.mask & libc::IN_IGNORED != 0 {
+                    self.dirs.remove(&event.wd);
+                    continue;
+                }
+
+                // The name is padded with NULs.
+                let name = &buf[start..i];
+                let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                let path = match self.dirs.get(&event.wd) {
+                    Some(dir) => dir.join(OsStr::from_bytes(name)),
+                    None => continue,
+                };
+
+                if event.mask & libc::IN_CREATE != 0 {
+                    changes.push(Change::Created(path));
+                } else if event.mask & libc::IN_DELETE != 0 {
+                    changes.push(Change::Removed(path));
+                } else if event.mask & libc::IN_MOVED_FROM != 0 {
+                    moves.insert(event.cookie, changes.len());
+                    changes.push(Change::Removed(path));
+                } else if event.mask & libc::IN_MOVED_TO != 0 {
+                    match moves.remove(&event.cookie) {
+                        Some(j) => {
+                            let from = match mem::replace(&mut changes[j], Change::Overflow) {
+                                Change::Removed(from) => from,
+                                _ => unreachable!("Moves are recorded as removals"),
+                            };
+                            changes[j] = Change::Moved { from, to: path };
+                        }
+                        None => changes.push(Change::Created(path)),
+                    }
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    pub fn new() -> io::Result<Self> {
+        Err(io::Error::other(
+            "Watching for changes is only supported on Linux",
+        ))
+    }
+
+    pub fn watch(&mut self, _dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn changes(&mut self) -> io::Result<Vec<Change>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    use std::fs::{create_dir_all, remove_file, rename, File};
+    use util::TempDir;
+
+    #[test]
+    fn test_changes() {
+        let dir = TempDir::new("watch");
+        let outside = TempDir::new("watch-out");
+        create_dir_all(dir.join("a")).unwrap();
+
+        let mut w = Watcher::new().unwrap();
+        w.watch(&dir).unwrap();
+        w.watch(&dir.join("a")).unwrap();
+        assert_eq!(Vec::<Change>::new(), w.changes().unwrap());
+
+        File::create(dir.join("f")).unwrap();
+        rename(dir.join("f"), dir.join("a/g")).unwrap();
+        rename(dir.join("a/g"), outside.join("g")).unwrap();
+        File::create(dir.join("h")).unwrap();
+        remove_file(dir.join("h")).unwrap();
+
+        let changes = w.changes();
+
+        assert_eq!(
+            vec![
+                Change::Created(dir.join("f")),
+                Change::Moved {
+                    from: dir.join("f"),
+                    to: dir.join("a/g"),
+                },
+                Change::Removed(dir.join("a/g")),
+                Change::Created(dir.join("h")),
+                Change::Removed(dir.join("h")),
+            ],
+            changes.unwrap()
+        );
+    }
+}